@@ -0,0 +1,147 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::{thread_rng, RngCore};
+use serde::{Deserialize, Deserializer};
+use tzhandhsake::{
+    encoding::{
+        bin::{self, deserialize_fixed, deserialize_fixed_ref, BuffVisitor},
+        direct::{self, Bytes},
+        dynamic::Dynamic,
+    },
+    p2p::message::BlockHeader,
+};
+
+/// Same header as `BlockHeader` decoded the way it used to be: byte per byte
+/// through `BuffVisitor` into owned buffers.
+#[derive(Deserialize)]
+struct PerByteHeader {
+    level: i32,
+    proto: u8,
+    #[serde(deserialize_with = "seq_hash")]
+    predecessor: [u8; 32],
+    timestamp: i64,
+    validation_pass: u8,
+    #[serde(deserialize_with = "seq_hash")]
+    operations_hash: [u8; 32],
+    fitness: Dynamic<Vec<Dynamic<Vec<u8>>>>,
+    #[serde(deserialize_with = "seq_hash")]
+    context: [u8; 32],
+    protocol_data: Vec<u8>,
+}
+
+fn seq_hash<'de, D>(deserializer: D) -> Result<[u8; 32], D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_seq(BuffVisitor::<32>)
+}
+
+/// Same header as `BlockHeader` for serde, whose derive can't prefix `fitness` with its size
+#[derive(Deserialize)]
+struct OwnedHeader {
+    level: i32,
+    proto: u8,
+    #[serde(deserialize_with = "deserialize_fixed")]
+    predecessor: [u8; 32],
+    timestamp: i64,
+    validation_pass: u8,
+    #[serde(deserialize_with = "deserialize_fixed")]
+    operations_hash: [u8; 32],
+    fitness: Dynamic<Vec<Dynamic<Bytes>>>,
+    #[serde(deserialize_with = "deserialize_fixed")]
+    context: [u8; 32],
+    protocol_data: Bytes,
+}
+
+/// Same header, borrowing its hashes and bytes from the input
+#[derive(Deserialize)]
+struct BorrowedHeader<'a> {
+    level: i32,
+    proto: u8,
+    #[serde(deserialize_with = "deserialize_fixed_ref")]
+    predecessor: &'a [u8; 32],
+    timestamp: i64,
+    validation_pass: u8,
+    #[serde(deserialize_with = "deserialize_fixed_ref")]
+    operations_hash: &'a [u8; 32],
+    #[serde(borrow)]
+    fitness: Dynamic<Vec<Dynamic<&'a [u8]>>>,
+    #[serde(deserialize_with = "deserialize_fixed_ref")]
+    context: &'a [u8; 32],
+    protocol_data: &'a [u8],
+}
+
+/// Reads every field of the decoded headers so none of the decoding is optimized away
+macro_rules! consume {
+    ($headers:expr) => {
+        for Dynamic(header) in $headers {
+            black_box(header.level);
+            black_box(header.proto);
+            black_box(&header.predecessor);
+            black_box(header.timestamp);
+            black_box(header.validation_pass);
+            black_box(&header.operations_hash);
+            black_box(&header.fitness);
+            black_box(&header.context);
+            black_box(&header.protocol_data);
+        }
+    };
+}
+
+/// `count` headers, each prefixed by its size like in `Block_header` messages
+fn batch(count: usize) -> Vec<u8> {
+    let mut rng = thread_rng();
+    let mut hash = || {
+        let mut bytes = [0; 32];
+        rng.fill_bytes(&mut bytes);
+        bytes
+    };
+    let headers: Vec<_> = (0..count)
+        .map(|level| {
+            Dynamic(BlockHeader {
+                level: level as i32,
+                proto: 1,
+                predecessor: hash().into(),
+                timestamp: 1_700_000_000 + level as i64,
+                validation_pass: 4,
+                operations_hash: hash().into(),
+                fitness: vec![
+                    Dynamic(Bytes(vec![2])),
+                    Dynamic(Bytes(level.to_be_bytes().to_vec())),
+                    Dynamic(Bytes(vec![])),
+                    Dynamic(Bytes(vec![0xff; 4])),
+                    Dynamic(Bytes(vec![0; 4])),
+                ],
+                context: hash().into(),
+                protocol_data: Bytes([hash(), hash(), hash()].concat()),
+            })
+        })
+        .collect();
+    direct::to_bytes_no_header(&headers).unwrap()
+}
+
+/// The derived `TezosEncoding` of `BlockHeader` and the former byte per byte serde path are
+/// the baselines, serde then decodes the same headers into owned values and borrowing from
+/// the input.
+fn criterion_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Block headers decoding");
+    for count in [100, 1000] {
+        let bytes = batch(count);
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(BenchmarkId::new("direct", count), &bytes, |b, bytes| {
+            b.iter(|| consume!(direct::from_bytes::<Vec<Dynamic<BlockHeader>>>(bytes).unwrap()))
+        });
+        group.bench_with_input(BenchmarkId::new("per byte", count), &bytes, |b, bytes| {
+            b.iter(|| consume!(bin::from_bytes::<Vec<Dynamic<PerByteHeader>>>(bytes).unwrap()))
+        });
+        group.bench_with_input(BenchmarkId::new("serde", count), &bytes, |b, bytes| {
+            b.iter(|| consume!(bin::from_bytes::<Vec<Dynamic<OwnedHeader>>>(bytes).unwrap()))
+        });
+        group.bench_with_input(BenchmarkId::new("borrowed", count), &bytes, |b, bytes| {
+            b.iter(|| consume!(bin::from_bytes::<Vec<Dynamic<BorrowedHeader>>>(bytes).unwrap()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
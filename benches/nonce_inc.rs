@@ -26,7 +26,7 @@ fn criterion_benchmark(c: &mut Criterion) {
     let mut rng = thread_rng();
     let mut nonce = [0u8; 24];
     rng.fill_bytes(&mut nonce);
-    let mut p2pnonce = Nonce::from(nonce.clone());
+    let mut p2pnonce = Nonce::from(nonce);
 
     group.bench_function("basic_inc", |b| b.iter(|| basic_inc(&mut nonce, 1)));
     group.bench_function("ref impl", |b| b.iter(|| p2pnonce.inc()));
@@ -0,0 +1,256 @@
+/// Base58Check encoding of Tezos hashes and keys.
+///
+/// Each kind of value has its own version prefix which gives the familiar leading
+/// characters (`B...` for block hashes, `tz1...` for ed25519 key hashes...), see octez
+/// `src/lib_crypto/base58.ml`.
+/// Types defined with `b58_hash!` are b58 strings in json and raw bytes in both binary encodings.
+use super::error::B58Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Prefix {
+    /// Version bytes prepended to the payload before encoding
+    pub version: &'static [u8],
+    /// Length of the payload, without version bytes and checksum
+    pub payload_length: usize,
+    /// Leading characters of every encoded value
+    pub readable: &'static str,
+}
+
+pub const BLOCK_HASH: Prefix = Prefix {
+    version: &[1, 52],
+    payload_length: 32,
+    readable: "B",
+};
+pub const OPERATION_HASH: Prefix = Prefix {
+    version: &[5, 116],
+    payload_length: 32,
+    readable: "o",
+};
+pub const OPERATION_LIST_HASH: Prefix = Prefix {
+    version: &[133, 233],
+    payload_length: 32,
+    readable: "Lo",
+};
+pub const OPERATION_LIST_LIST_HASH: Prefix = Prefix {
+    version: &[29, 159, 109],
+    payload_length: 32,
+    readable: "LLo",
+};
+pub const PROTOCOL_HASH: Prefix = Prefix {
+    version: &[2, 170],
+    payload_length: 32,
+    readable: "P",
+};
+pub const CONTEXT_HASH: Prefix = Prefix {
+    version: &[79, 199],
+    payload_length: 32,
+    readable: "Co",
+};
+pub const CHAIN_ID: Prefix = Prefix {
+    version: &[87, 82, 0],
+    payload_length: 4,
+    readable: "Net",
+};
+pub const CRYPTOBOX_PUBLIC_KEY_HASH: Prefix = Prefix {
+    version: &[153, 103],
+    payload_length: 16,
+    readable: "id",
+};
+pub const ED25519_PUBLIC_KEY_HASH: Prefix = Prefix {
+    version: &[6, 161, 159],
+    payload_length: 20,
+    readable: "tz1",
+};
+pub const SECP256K1_PUBLIC_KEY_HASH: Prefix = Prefix {
+    version: &[6, 161, 161],
+    payload_length: 20,
+    readable: "tz2",
+};
+pub const P256_PUBLIC_KEY_HASH: Prefix = Prefix {
+    version: &[6, 161, 164],
+    payload_length: 20,
+    readable: "tz3",
+};
+
+/// Encodes `payload` with the version bytes of `prefix` and a checksum.
+pub fn encode(prefix: &Prefix, payload: &[u8]) -> String {
+    bs58::encode([prefix.version, payload].concat())
+        .with_check()
+        .into_string()
+}
+
+/// Decodes `s`, checking the checksum, the version bytes and the payload length.
+pub fn decode(prefix: &Prefix, s: &str) -> Result<Vec<u8>, B58Error> {
+    let decoded = bs58::decode(s)
+        .with_check(None)
+        .into_vec()
+        .map_err(|e| match e {
+            bs58::decode::Error::InvalidChecksum { .. } => B58Error::InvalidChecksum,
+            e => B58Error::InvalidBase58(e.to_string()),
+        })?;
+    let payload =
+        decoded
+            .strip_prefix(prefix.version)
+            .ok_or_else(|| B58Error::UnexpectedPrefix {
+                expected: prefix.readable,
+                value: s.to_string(),
+            })?;
+    if payload.len() != prefix.payload_length {
+        return Err(B58Error::InvalidLength {
+            expected: prefix.payload_length,
+            actual: payload.len(),
+        });
+    }
+    Ok(payload.to_vec())
+}
+
+/// Defines a fixed size hash newtype encoded in Base58Check with the given prefix.
+macro_rules! b58_hash {
+    ($(#[$meta:meta])* $name:ident, $prefix:expr, $size:literal) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name([u8; $size]);
+
+        impl $name {
+            pub const PREFIX: $crate::encoding::b58::Prefix = $prefix;
+
+            pub fn to_b58check(&self) -> String {
+                $crate::encoding::b58::encode(&Self::PREFIX, &self.0)
+            }
+
+            pub fn from_b58check(s: &str) -> Result<Self, $crate::encoding::error::B58Error> {
+                let payload = $crate::encoding::b58::decode(&Self::PREFIX, s)?;
+                let mut bytes = [0; $size];
+                bytes.copy_from_slice(&payload);
+                Ok(Self(bytes))
+            }
+        }
+
+        impl From<[u8; $size]> for $name {
+            fn from(value: [u8; $size]) -> Self {
+                Self(value)
+            }
+        }
+
+        impl AsRef<[u8]> for $name {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(&self.to_b58check())
+            }
+        }
+
+        impl std::fmt::Debug for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}({})", stringify!($name), self.to_b58check())
+            }
+        }
+
+        impl std::str::FromStr for $name {
+            type Err = $crate::encoding::error::B58Error;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                Self::from_b58check(s)
+            }
+        }
+
+        impl serde::Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                if serializer.is_human_readable() {
+                    serializer.serialize_str(&self.to_b58check())
+                } else {
+                    serializer.serialize_bytes(&self.0)
+                }
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                if deserializer.is_human_readable() {
+                    let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+                    Self::from_b58check(&s).map_err(serde::de::Error::custom)
+                } else {
+                    $crate::encoding::bin::deserialize_fixed(deserializer).map(Self)
+                }
+            }
+        }
+
+        impl $crate::encoding::direct::TezosEncoding for $name {
+            fn encode(
+                &self,
+                out: &mut $crate::encoding::direct::BinWriter,
+            ) -> $crate::encoding::error::Result<()> {
+                $crate::encoding::direct::TezosEncoding::encode(&self.0, out)
+            }
+
+            fn decode(
+                input: &mut $crate::encoding::direct::BinReader<'_>,
+            ) -> $crate::encoding::error::Result<Self> {
+                input.read_array().map(Self)
+            }
+
+            fn schema() -> $crate::encoding::schema::Schema {
+                $crate::encoding::schema::Schema::Bytes { size: Some($size) }
+            }
+        }
+    };
+}
+
+pub(crate) use b58_hash;
+
+#[cfg(test)]
+mod tests {
+    use crate::encoding::error::B58Error;
+
+    use super::{decode, encode, BLOCK_HASH, CHAIN_ID, ED25519_PUBLIC_KEY_HASH};
+
+    #[test]
+    fn it_decodes_known_values() {
+        assert_eq!(
+            vec![0x7a, 0x06, 0xa7, 0x70],
+            decode(&CHAIN_ID, "NetXdQprcVkpaWU").unwrap()
+        );
+        let tz1 = decode(
+            &ED25519_PUBLIC_KEY_HASH,
+            "tz1KqTpEZ7Yob7QbPE4Hy4Wo8fHG8LhKxZSx",
+        )
+        .unwrap();
+        assert_eq!(
+            "tz1KqTpEZ7Yob7QbPE4Hy4Wo8fHG8LhKxZSx",
+            encode(&ED25519_PUBLIC_KEY_HASH, &tz1)
+        );
+    }
+
+    #[test]
+    fn it_encodes_with_readable_prefix() {
+        assert!(encode(&BLOCK_HASH, &[0; 32]).starts_with(BLOCK_HASH.readable));
+        assert!(encode(&BLOCK_HASH, &[0xff; 32]).starts_with(BLOCK_HASH.readable));
+        assert!(encode(&CHAIN_ID, &[0xff; 4]).starts_with(CHAIN_ID.readable));
+    }
+
+    #[test]
+    fn it_rejects_invalid_values() {
+        assert!(matches!(
+            decode(&CHAIN_ID, "NetXdQprcVkpaWV"),
+            Err(B58Error::InvalidChecksum)
+        ));
+        assert!(matches!(
+            decode(&BLOCK_HASH, "NetXdQprcVkpaWU"),
+            Err(B58Error::UnexpectedPrefix { expected: "B", .. })
+        ));
+        assert!(matches!(
+            decode(&BLOCK_HASH, "0OIl"),
+            Err(B58Error::InvalidBase58(_))
+        ));
+    }
+}
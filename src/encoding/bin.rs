@@ -1,13 +1,20 @@
 /// Generic module for binary serialization of messages exchanged in the Tezos p2p protocol.
-/// Note: currently minimally implemented to just do the handshake.
-/// Note: Very inefficient: serde (or I can't figure out a way) doesn't offer an obivous
-/// way for handling fixed sized arrays during serialization.
+/// Follows octez data-encoding:
+/// - integers are big-endian, `u8`..`u64` and `i8`..`i64` have a fixed width
+/// - booleans and `Option` tags are 0x00 or 0xff
+/// - strings are prefixed by their u32 length, at most 2^30 - 1 like octez
+/// - enum variants are prefixed by a u8 tag, their index, or a u16 one for enums renamed to
+///   [`U16_TAGGED`]
+/// - sequences and maps have no length prefix, they span until the end of the input, unless
+///   they are wrapped in one of the `encoding::dynamic` types
+/// - bytes are written as is, they are read either as fixed size arrays with
+///   [`deserialize_fixed`] or as the rest of the input
+///
+/// Deserialization borrows from the input: `&str`, `&[u8]` and, through
+/// [`deserialize_fixed_ref`], `&[u8; N]` fields point into the decoded buffer.
 ///
-use std::{io, usize};
-
 use serde::{
-    de::{self, SeqAccess, Visitor},
-    forward_to_deserialize_any,
+    de::{self, EnumAccess, IntoDeserializer, MapAccess, SeqAccess, VariantAccess, Visitor},
     ser::{
         SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
         SerializeTupleStruct, SerializeTupleVariant,
@@ -15,7 +22,10 @@ use serde::{
     Deserialize, Deserializer, Serialize, Serializer,
 };
 
-use super::error::{Error, Result};
+use super::{
+    dynamic::{SizePrefix, BOUNDED},
+    error::{Error, Result},
+};
 
 /// Serializes plain data in the packed binary format of Tezos P2P protocol, the data is fully returned
 /// and ready to be sent to peers as is.
@@ -35,10 +45,15 @@ where
     let mut output = vec![0u8; 2];
     output.reserve(1024);
 
-    let mut ser = TezosBinSerializer { output, length: 0 };
+    let mut ser = TezosBinSerializer { output };
     value.serialize(&mut ser)?;
-    ser.output[0] = (ser.length >> 8) as u8;
-    ser.output[1] = ser.length as u8;
+    // only the header limits the size, values themselves are bounded by their own prefixes
+    let size = ser.output.len() - 2;
+    let header = u16::try_from(size).map_err(|_| Error::SizePrefixOverflow {
+        size,
+        max: SizePrefix::U16.max(),
+    })?;
+    ser.output[..2].copy_from_slice(&header.to_be_bytes());
     Ok(ser.output)
 }
 pub fn to_bytes_no_header<T>(value: &T) -> Result<Vec<u8>>
@@ -48,7 +63,7 @@ where
     let mut output = vec![0u8; 0];
     output.reserve(1024);
 
-    let mut ser = TezosBinSerializer { output, length: 0 };
+    let mut ser = TezosBinSerializer { output };
     value.serialize(&mut ser)?;
     Ok(ser.output)
 }
@@ -59,24 +74,44 @@ where
 /// during the handshake to compute nonces for NaCl encrypting and signature.
 struct TezosBinSerializer {
     output: Vec<u8>,
-    length: u16,
 }
 
 impl TezosBinSerializer {
-    /// increments length and check for overflow
-    fn incr_length(&mut self, added: u16) -> Result<()> {
-        if self.length + added < self.length {
-            Err(Error::SizeOverflow {
-                before: self.length,
-                added,
-            })
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.output.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Variant tag, u8 unless the enum is renamed to `U16_TAGGED`
+    fn write_tag(&mut self, name: &'static str, variant_index: u32) -> Result<()> {
+        if name == U16_TAGGED {
+            let tag =
+                u16::try_from(variant_index).map_err(|_| Error::TagOverflow(variant_index))?;
+            self.write(&tag.to_be_bytes())
         } else {
-            self.length += added;
-            Ok(())
+            let tag = u8::try_from(variant_index).map_err(|_| Error::TagOverflow(variant_index))?;
+            self.write(&[tag])
         }
     }
 }
 
+/// Strings and bytes can't be longer than a u30, the default size prefix of octez
+fn check_u30(size: usize) -> Result<()> {
+    let max = SizePrefix::U30.max();
+    if size > max {
+        return Err(Error::SizePrefixOverflow { size, max });
+    }
+    Ok(())
+}
+
+/// Name to give to enums with more than 256 variants, with `#[serde(rename = "...")]`,
+/// so that their tags are encoded on 2 bytes.
+pub const U16_TAGGED: &str = "tezos_u16_tagged";
+
+/// octez `Option` tags, the same as booleans
+const NONE_TAG: u8 = 0;
+const SOME_TAG: u8 = 0xff;
+
 impl<'a> Serializer for &'a mut TezosBinSerializer {
     type Ok = ();
     type Error = Error;
@@ -85,7 +120,7 @@ impl<'a> Serializer for &'a mut TezosBinSerializer {
 
     type SerializeTuple = Self;
 
-    type SerializeTupleStruct = Self;
+    type SerializeTupleStruct = TupleStruct<'a>;
 
     type SerializeTupleVariant = Self;
 
@@ -95,123 +130,119 @@ impl<'a> Serializer for &'a mut TezosBinSerializer {
 
     type SerializeStructVariant = Self;
 
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
     fn serialize_str(self, v: &str) -> Result<Self::Ok> {
-        if v.len() >= u16::max_value() as usize {
-            Err(Error::StringTooLong)
-        } else {
-            let len = v.len() as u16;
-            self.incr_length(len)?;
-            // note that this is not a mistake
-            // for some reasons Tezos encodes strings length as u32
-            self.serialize_u16(0)?;
-            self.serialize_u16(len)?;
-            io::Write::write(&mut self.output, v.as_bytes())?;
-            Ok(())
-        }
+        check_u30(v.len())?;
+        // note that this is not a mistake
+        // for some reasons Tezos encodes strings length as u32
+        self.serialize_u32(v.len() as u32)?;
+        self.write(v.as_bytes())
     }
+    /// octez booleans are a single byte, 0xff for true and 0x00 for false
     fn serialize_bool(self, v: bool) -> Result<Self::Ok> {
-        // FIXME should be the opposite
-        self.serialize_bytes(if v { &[0] } else { &[1] })
+        self.serialize_u8(if v { 0xff } else { 0 })
     }
 
-    fn serialize_i8(self, _v: i8) -> Result<Self::Ok> {
-        unimplemented!()
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok> {
+        self.write(&v.to_be_bytes())
     }
 
-    fn serialize_i16(self, _v: i16) -> Result<Self::Ok> {
-        unimplemented!()
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok> {
+        self.write(&v.to_be_bytes())
     }
 
-    fn serialize_i32(self, _v: i32) -> Result<Self::Ok> {
-        unimplemented!()
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok> {
+        self.write(&v.to_be_bytes())
     }
 
-    fn serialize_i64(self, _v: i64) -> Result<Self::Ok> {
-        unimplemented!()
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok> {
+        self.write(&v.to_be_bytes())
     }
 
-    fn serialize_u8(self, _v: u8) -> Result<Self::Ok> {
-        unimplemented!()
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok> {
+        self.write(&[v])
     }
 
     fn serialize_u16(self, v: u16) -> Result<Self::Ok> {
-        io::Write::write(&mut self.output, &[(v >> 8) as u8, v as u8])?;
-        self.incr_length(2)
+        self.write(&v.to_be_bytes())
     }
 
-    fn serialize_u32(self, _v: u32) -> Result<Self::Ok> {
-        unimplemented!()
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok> {
+        self.write(&v.to_be_bytes())
     }
 
-    fn serialize_u64(self, _v: u64) -> Result<Self::Ok> {
-        unimplemented!()
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok> {
+        self.write(&v.to_be_bytes())
     }
 
     fn serialize_f32(self, _v: f32) -> Result<Self::Ok> {
-        unimplemented!()
+        Err(Error::Unsupported("f32, octez floats are f64"))
     }
 
-    fn serialize_f64(self, _v: f64) -> Result<Self::Ok> {
-        unimplemented!()
+    fn serialize_f64(self, v: f64) -> Result<Self::Ok> {
+        self.write(&v.to_be_bytes())
     }
 
     fn serialize_char(self, _v: char) -> Result<Self::Ok> {
-        unimplemented!()
+        Err(Error::Unsupported("char"))
     }
 
     fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok> {
-        let len = v.len() as u16;
-        self.incr_length(len)?;
-        io::Write::write(&mut self.output, v)?;
-        Ok(())
+        check_u30(v.len())?;
+        self.write(v)
     }
 
     fn serialize_none(self) -> Result<Self::Ok> {
-        unimplemented!()
+        self.serialize_u8(NONE_TAG)
     }
 
-    fn serialize_some<T: ?Sized>(self, _value: &T) -> Result<Self::Ok>
+    fn serialize_some<T>(self, value: &T) -> Result<Self::Ok>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
-        unimplemented!()
+        self.serialize_u8(SOME_TAG)?;
+        value.serialize(self)
     }
 
     fn serialize_unit(self) -> Result<Self::Ok> {
-        unimplemented!()
+        Ok(())
     }
 
     fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok> {
-        unimplemented!()
+        Ok(())
     }
 
     fn serialize_unit_variant(
         self,
-        _name: &'static str,
-        _variant_index: u32,
+        name: &'static str,
+        variant_index: u32,
         _variant: &'static str,
     ) -> Result<Self::Ok> {
-        unimplemented!()
+        self.write_tag(name, variant_index)
     }
 
-    fn serialize_newtype_struct<T: ?Sized>(self, _name: &'static str, value: &T) -> Result<Self::Ok>
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<Self::Ok>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
         value.serialize(self)
     }
 
-    fn serialize_newtype_variant<T: ?Sized>(
+    fn serialize_newtype_variant<T>(
         self,
-        _name: &'static str,
-        _variant_index: u32,
+        name: &'static str,
+        variant_index: u32,
         _variant: &'static str,
-        _value: &T,
+        value: &T,
     ) -> Result<Self::Ok>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
-        unimplemented!()
+        self.write_tag(name, variant_index)?;
+        value.serialize(self)
     }
 
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
@@ -224,19 +255,34 @@ impl<'a> Serializer for &'a mut TezosBinSerializer {
 
     fn serialize_tuple_struct(
         self,
-        _name: &'static str,
-        _len: usize,
+        name: &'static str,
+        len: usize,
     ) -> Result<Self::SerializeTupleStruct> {
-        Ok(self)
+        let wrapper = if let Some(prefix) = SizePrefix::from_name(name) {
+            // the size is only known at the end
+            self.write(&[0; 4][..prefix.bytes()])?;
+            Wrapper::Dynamic(prefix)
+        } else if name == BOUNDED {
+            Wrapper::Bounded(len)
+        } else {
+            Wrapper::None
+        };
+        let start = self.output.len();
+        Ok(TupleStruct {
+            ser: self,
+            wrapper,
+            start,
+        })
     }
 
     fn serialize_tuple_variant(
         self,
-        _name: &'static str,
-        _variant_index: u32,
+        name: &'static str,
+        variant_index: u32,
         _variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
+        self.write_tag(name, variant_index)?;
         Ok(self)
     }
 
@@ -250,20 +296,21 @@ impl<'a> Serializer for &'a mut TezosBinSerializer {
 
     fn serialize_struct_variant(
         self,
-        _name: &'static str,
-        _variant_index: u32,
+        name: &'static str,
+        variant_index: u32,
         _variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStructVariant> {
+        self.write_tag(name, variant_index)?;
         Ok(self)
     }
 }
-impl<'a> SerializeSeq for &'a mut TezosBinSerializer {
+impl SerializeSeq for &mut TezosBinSerializer {
     type Ok = ();
     type Error = Error;
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
         value.serialize(&mut **self)
     }
@@ -272,12 +319,12 @@ impl<'a> SerializeSeq for &'a mut TezosBinSerializer {
     }
 }
 
-impl<'a> SerializeTuple for &'a mut TezosBinSerializer {
+impl SerializeTuple for &mut TezosBinSerializer {
     type Ok = ();
     type Error = Error;
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
         value.serialize(&mut **self)
     }
@@ -286,26 +333,57 @@ impl<'a> SerializeTuple for &'a mut TezosBinSerializer {
     }
 }
 
-impl<'a> SerializeTupleStruct for &'a mut TezosBinSerializer {
+/// Tuple structs, which can be `encoding::dynamic` wrappers
+struct TupleStruct<'a> {
+    ser: &'a mut TezosBinSerializer,
+    wrapper: Wrapper,
+    /// where the fields start in the output
+    start: usize,
+}
+
+enum Wrapper {
+    None,
+    Dynamic(SizePrefix),
+    Bounded(usize),
+}
+
+impl SerializeTupleStruct for TupleStruct<'_> {
     type Ok = ();
     type Error = Error;
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
-        value.serialize(&mut **self)
+        value.serialize(&mut *self.ser)
     }
     fn end(self) -> Result<Self::Ok> {
-        Ok(())
+        let size = self.ser.output.len() - self.start;
+        match self.wrapper {
+            Wrapper::None => Ok(()),
+            Wrapper::Dynamic(prefix) => {
+                if size > prefix.max() {
+                    return Err(Error::SizePrefixOverflow {
+                        size,
+                        max: prefix.max(),
+                    });
+                }
+                let bytes = (size as u32).to_be_bytes();
+                self.ser.output[self.start - prefix.bytes()..self.start]
+                    .copy_from_slice(&bytes[4 - prefix.bytes()..]);
+                Ok(())
+            }
+            Wrapper::Bounded(bound) if size > bound => Err(Error::BoundExceeded { bound, size }),
+            Wrapper::Bounded(_) => Ok(()),
+        }
     }
 }
 
-impl<'a> SerializeTupleVariant for &'a mut TezosBinSerializer {
+impl SerializeTupleVariant for &mut TezosBinSerializer {
     type Ok = ();
     type Error = Error;
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
         value.serialize(&mut **self)
     }
@@ -314,39 +392,33 @@ impl<'a> SerializeTupleVariant for &'a mut TezosBinSerializer {
     }
 }
 
-impl<'a> SerializeMap for &'a mut TezosBinSerializer {
+/// Maps are lists of (key, value) pairs
+impl SerializeMap for &mut TezosBinSerializer {
     type Ok = ();
     type Error = Error;
-    fn serialize_key<T: ?Sized>(&mut self, _key: &T) -> Result<()>
-    where
-        T: serde::Serialize,
-    {
-        unimplemented!()
-    }
-    fn serialize_value<T: ?Sized>(&mut self, _value: &T) -> Result<()>
+    fn serialize_key<T>(&mut self, key: &T) -> Result<()>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
-        unimplemented!()
+        key.serialize(&mut **self)
     }
-    fn serialize_entry<K: ?Sized, V: ?Sized>(&mut self, _key: &K, _value: &V) -> Result<()>
+    fn serialize_value<T>(&mut self, value: &T) -> Result<()>
     where
-        K: serde::Serialize,
-        V: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
-        unimplemented!()
+        value.serialize(&mut **self)
     }
     fn end(self) -> Result<Self::Ok> {
-        unimplemented!()
+        Ok(())
     }
 }
 
-impl<'a> SerializeStruct for &'a mut TezosBinSerializer {
+impl SerializeStruct for &mut TezosBinSerializer {
     type Ok = ();
     type Error = Error;
-    fn serialize_field<T: ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, _key: &'static str, value: &T) -> Result<()>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
         value.serialize(&mut **self)
     }
@@ -358,12 +430,12 @@ impl<'a> SerializeStruct for &'a mut TezosBinSerializer {
     }
 }
 
-impl<'a> SerializeStructVariant for &'a mut TezosBinSerializer {
+impl SerializeStructVariant for &mut TezosBinSerializer {
     type Ok = ();
     type Error = Error;
-    fn serialize_field<T: ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, _key: &'static str, value: &T) -> Result<()>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
         value.serialize(&mut **self)
     }
@@ -375,6 +447,27 @@ impl<'a> SerializeStructVariant for &'a mut TezosBinSerializer {
     }
 }
 
+/// Magic name of fixed size byte arrays, the tuple struct length is their size.
+pub(crate) const FIXED_BYTES: &str = "tezos_fixed_bytes";
+
+/// Reads `N` bytes at once, other formats see a sequence of `N` bytes.
+pub fn deserialize_fixed<'de, D, const N: usize>(deserializer: D) -> Result<[u8; N], D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_tuple_struct(FIXED_BYTES, N, BuffVisitor::<N>)
+}
+
+/// Borrows `N` bytes from the input, only the binary deserializer can lend them.
+pub fn deserialize_fixed_ref<'de, D, const N: usize>(
+    deserializer: D,
+) -> Result<&'de [u8; N], D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_tuple_struct(FIXED_BYTES, N, BorrowedBuffVisitor::<N>)
+}
+
 /// `serde::de::Visitor` for fixed sized byte buffers.
 pub struct BuffVisitor<const N: usize>;
 
@@ -383,6 +476,13 @@ impl<'de, const N: usize> Visitor<'de> for BuffVisitor<N> {
     fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         formatter.write_fmt(format_args!("expected an array of bytes of size {}", N))
     }
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        v.try_into()
+            .map_err(|_| de::Error::invalid_length(v.len(), &self))
+    }
     fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
     where
         A: SeqAccess<'de>,
@@ -399,10 +499,26 @@ impl<'de, const N: usize> Visitor<'de> for BuffVisitor<N> {
     }
 }
 
-/// Deserializes a structure from a byte array
-pub fn from_bytes<T>(input: &mut [u8]) -> Result<T>
+struct BorrowedBuffVisitor<const N: usize>;
+
+impl<'de, const N: usize> Visitor<'de> for BorrowedBuffVisitor<N> {
+    type Value = &'de [u8; N];
+    fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter.write_fmt(format_args!("{} bytes borrowed from the input", N))
+    }
+    fn visit_borrowed_bytes<E>(self, v: &'de [u8]) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        v.try_into()
+            .map_err(|_| de::Error::invalid_length(v.len(), &self))
+    }
+}
+
+/// Deserializes a structure from a byte array, it may borrow from `input`.
+pub fn from_bytes<'de, T>(input: &'de [u8]) -> Result<T>
 where
-    T: for<'de> Deserialize<'de>,
+    T: Deserialize<'de>,
 {
     let mut deserializer = TezosBinDeserializer::from_bytes(input);
     let t = T::deserialize(&mut deserializer)?;
@@ -424,72 +540,106 @@ impl<'de> TezosBinDeserializer<'de> {
     fn is_empty(&self) -> bool {
         self.input.len() == 0
     }
-    fn next(&mut self) -> Option<u8> {
-        if self.input.is_empty() {
-            None
-        } else {
-            let elt = self.input[0];
-            self.input = &self.input[1..];
-            Some(elt)
-        }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(self.ensure_bytes(N)?);
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        let [b] = self.read_array()?;
+        Ok(b)
     }
 
     fn read_u16(&mut self) -> Result<u16> {
-        if self.input.len() < 2 {
-            Err(Error::UnsufficentBytes)
-        } else {
-            let mut v = (self.input[0] as u16) << 8;
-            v |= self.input[1] as u16;
-            self.input = &self.input[2..];
-            Ok(v)
-        }
+        Ok(u16::from_be_bytes(self.read_array()?))
     }
 
     fn read_bool(&mut self) -> Result<bool> {
-        if self.input.is_empty() {
-            Err(Error::UnsufficentBytes)
+        match self.read_u8()? {
+            0 => Ok(false),
+            0xff => Ok(true),
+            b => Err(Error::InvalidBool(b)),
+        }
+    }
+
+    /// Variant tag, u8 unless the enum is renamed to `U16_TAGGED`
+    fn read_tag(&mut self, name: &'static str) -> Result<u32> {
+        if name == U16_TAGGED {
+            self.read_u16().map(u32::from)
         } else {
-            let b = self.input[0] == 0; // FIXME should be a 1 but this is a hack just for ack
-            self.input = &self.input[1..];
-            Ok(b)
+            self.read_u8().map(u32::from)
         }
     }
 
-    fn ensure_bytes(&mut self, size: usize) -> Result<Vec<u8>> {
+    fn ensure_bytes(&mut self, size: usize) -> Result<&'de [u8]> {
         if self.input.len() < size {
             Err(Error::UnsufficentBytes)
         } else {
-            let mut buff = vec![0; size];
-            buff.copy_from_slice(&self.input[..size]);
-            self.input = &self.input[size..];
-            Ok(buff)
+            let (bytes, rest) = self.input.split_at(size);
+            self.input = rest;
+            Ok(bytes)
         }
     }
 }
 
-impl<'de, 'a> Deserializer<'de> for &'a mut TezosBinDeserializer<'de> {
+impl<'de> Deserializer<'de> for &mut TezosBinDeserializer<'de> {
     type Error = Error;
 
-    forward_to_deserialize_any! {
-        i8 i16 i32 i64 u32 u64 f32 f64 char str bytes byte_buf option unit
-        unit_struct tuple tuple_struct map enum identifier ignored_any
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    /// The binary encoding is not self describing, the type must drive deserialization.
+    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Err(Error::Unsupported("self describing deserialization"))
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let b = self.read_bool()?;
+        visitor.visit_bool(b)
+    }
+
+    fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_i8(i8::from_be_bytes(self.read_array()?))
+    }
+
+    fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_i16(i16::from_be_bytes(self.read_array()?))
     }
-    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+
+    fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let _ = visitor;
-        unimplemented!(
-            r#"If you'd like to further enhance this protocol consider first implementing a more performant one similar to binary_serde but with support of extra self describing data (such as variable sized strings)"#
-        )
+        visitor.visit_i32(i32::from_be_bytes(self.read_array()?))
+    }
+
+    fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_i64(i64::from_be_bytes(self.read_array()?))
     }
 
-    /// Quite inefficient!!
     fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let b = self.next().ok_or_else(|| Error::UnsufficentBytes)?;
+        let b = self.read_u8()?;
         visitor.visit_u8(b)
     }
 
@@ -501,27 +651,104 @@ impl<'de, 'a> Deserializer<'de> for &'a mut TezosBinDeserializer<'de> {
         visitor.visit_u16(v)
     }
 
+    fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_u32(u32::from_be_bytes(self.read_array()?))
+    }
+
+    fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_u64(u64::from_be_bytes(self.read_array()?))
+    }
+
+    fn deserialize_f32<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Err(Error::Unsupported("f32, octez floats are f64"))
+    }
+
+    fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_f64(f64::from_be_bytes(self.read_array()?))
+    }
+
+    fn deserialize_char<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Err(Error::Unsupported("char"))
+    }
+
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let size = u32::from_be_bytes(self.read_array()?) as usize;
+        check_u30(size)?;
+        let buff = self.ensure_bytes(size)?;
+        visitor.visit_borrowed_str(std::str::from_utf8(buff)?)
+    }
+
     fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let zeros = self.read_u16()?;
-        assert_eq!(
-            0, zeros,
-            "the size of strings should not be larger than a full packet"
-        );
-        let size = self.read_u16()?;
-        let buff = self.ensure_bytes(size as usize)?;
-        let s = String::from_utf8(buff)?;
-        visitor.visit_string(s)
+        self.deserialize_str(visitor)
     }
 
-    fn deserialize_newtype_struct<V>(self, name: &'static str, visitor: V) -> Result<V::Value>
+    /// Bytes without a known size are the rest of the input
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let _ = name;
-        visitor.visit_seq(self)
+        let bytes = self.ensure_bytes(self.input.len())?;
+        visitor.visit_borrowed_bytes(bytes)
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.read_u8()? {
+            NONE_TAG => visitor.visit_none(),
+            SOME_TAG => visitor.visit_some(self),
+            tag => Err(Error::InvalidOptionTag(tag)),
+        }
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
     }
 
     fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
@@ -531,36 +758,421 @@ impl<'de, 'a> Deserializer<'de> for &'a mut TezosBinDeserializer<'de> {
         visitor.visit_seq(self)
     }
 
-    fn deserialize_struct<V>(
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_seq(Fields { de: self, len })
+    }
+
+    /// Also decodes the `encoding::dynamic` wrappers and fixed size byte arrays
+    fn deserialize_tuple_struct<V>(
         self,
         name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        if name == FIXED_BYTES {
+            return visitor.visit_borrowed_bytes(self.ensure_bytes(len)?);
+        }
+        if let Some(prefix) = SizePrefix::from_name(name) {
+            let mut bytes = [0; 4];
+            bytes[4 - prefix.bytes()..].copy_from_slice(self.ensure_bytes(prefix.bytes())?);
+            let size = u32::from_be_bytes(bytes) as usize;
+            if size > prefix.max() {
+                return Err(Error::SizePrefixOverflow {
+                    size,
+                    max: prefix.max(),
+                });
+            }
+            let mut inner = TezosBinDeserializer::from_bytes(self.ensure_bytes(size)?);
+            let value = visitor.visit_seq(Fields {
+                de: &mut inner,
+                len,
+            })?;
+            if inner.is_empty() {
+                Ok(value)
+            } else {
+                Err(Error::DynamicSizeMismatch {
+                    declared: size,
+                    read: size - inner.input.len(),
+                })
+            }
+        } else if name == BOUNDED {
+            let before = self.input.len();
+            let value = visitor.visit_seq(Fields {
+                de: &mut *self,
+                len: 1,
+            })?;
+            let size = before - self.input.len();
+            if size > len {
+                Err(Error::BoundExceeded { bound: len, size })
+            } else {
+                Ok(value)
+            }
+        } else {
+            visitor.visit_seq(Fields { de: self, len })
+        }
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_map(self)
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
         fields: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let _ = name;
-        let _ = fields;
-        visitor.visit_seq(self)
+        visitor.visit_seq(Fields {
+            de: self,
+            len: fields.len(),
+        })
     }
 
-    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let b = self.read_bool()?;
-        visitor.visit_bool(b)
+        let tag = self.read_tag(name)?;
+        visitor.visit_enum(Variant { de: self, tag })
+    }
+
+    fn deserialize_identifier<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Err(Error::Unsupported(
+            "identifiers, fields and variants have no name",
+        ))
+    }
+
+    fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Err(Error::Unsupported("ignored values, their size is unknown"))
     }
 }
 
 impl<'de> SeqAccess<'de> for TezosBinDeserializer<'de> {
     type Error = Error;
 
+    /// Sequences have no length prefix, they end with the input. This is how octez
+    /// `Variable.list` are read at the end of a message.
+    fn next_element_seed<S>(&mut self, seed: S) -> Result<Option<S::Value>>
+    where
+        S: de::DeserializeSeed<'de>,
+    {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let before = self.input.len();
+        let value = seed.deserialize(&mut *self)?;
+        // an element without bytes, like `()`, would be read forever
+        if self.input.len() == before {
+            return Err(Error::ZeroSizedElement);
+        }
+        Ok(Some(value))
+    }
+}
+
+impl<'de> MapAccess<'de> for TezosBinDeserializer<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: de::DeserializeSeed<'de>,
+    {
+        self.next_element_seed(seed)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+    where
+        V: de::DeserializeSeed<'de>,
+    {
+        seed.deserialize(self)
+    }
+}
+
+/// Fields of tuples, structs and variants, their number is known.
+struct Fields<'a, 'de> {
+    de: &'a mut TezosBinDeserializer<'de>,
+    len: usize,
+}
+
+impl<'de> SeqAccess<'de> for Fields<'_, 'de> {
+    type Error = Error;
+
     fn next_element_seed<S>(&mut self, seed: S) -> Result<Option<S::Value>>
     where
         S: de::DeserializeSeed<'de>,
     {
-        seed.deserialize(self).map(Some)
+        if self.len == 0 {
+            Ok(None)
+        } else {
+            self.len -= 1;
+            seed.deserialize(&mut *self.de).map(Some)
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+/// Enum variant identified by its tag
+struct Variant<'a, 'de> {
+    de: &'a mut TezosBinDeserializer<'de>,
+    tag: u32,
+}
+
+impl<'a, 'de> EnumAccess<'de> for Variant<'a, 'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<S>(self, seed: S) -> Result<(S::Value, Self::Variant)>
+    where
+        S: de::DeserializeSeed<'de>,
+    {
+        let variant = seed.deserialize(IntoDeserializer::<Error>::into_deserializer(self.tag))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for Variant<'_, 'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<S>(self, seed: S) -> Result<S::Value>
+    where
+        S: de::DeserializeSeed<'de>,
+    {
+        seed.deserialize(self.de)
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_seq(Fields { de: self.de, len })
+    }
+
+    fn struct_variant<V>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_seq(Fields {
+            de: self.de,
+            len: fields.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use anyhow::Result;
+    use serde::{Deserialize, Serialize};
+
+    use crate::encoding::error::Error;
+
+    use super::{
+        deserialize_fixed, deserialize_fixed_ref, from_bytes, to_bytes, to_bytes_no_header,
+    };
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Primitives {
+        i8: i8,
+        i16: i16,
+        i32: i32,
+        i64: i64,
+        u8: u8,
+        u32: u32,
+        u64: u64,
+        f64: f64,
+        unit: (),
+        none: Option<u16>,
+        some: Option<u16>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Message {
+        Unit,
+        Newtype(u16),
+        Tuple(u8, bool),
+        Struct { a: u8 },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    /// Tagged on 2 bytes, `U16_TAGGED`
+    #[serde(rename = "tezos_u16_tagged")]
+    enum Wide {
+        A,
+        B(u8),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Borrowed<'a> {
+        name: &'a str,
+        #[serde(deserialize_with = "deserialize_fixed_ref")]
+        hash: &'a [u8; 4],
+        #[serde(deserialize_with = "deserialize_fixed")]
+        copied: [u8; 2],
+        rest: &'a [u8],
+    }
+
+    fn round_trip<T>(value: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize + for<'de> Deserialize<'de> + PartialEq + std::fmt::Debug,
+    {
+        let bytes = to_bytes_no_header(value)?;
+        let encoded = bytes.clone();
+        assert_eq!(value, &from_bytes::<T>(&bytes)?);
+        Ok(encoded)
+    }
+
+    #[test]
+    fn it_encodes_primitives_big_endian() -> Result<()> {
+        let bytes = round_trip(&Primitives {
+            i8: -2,
+            i16: -3,
+            i32: 0x0102_0304,
+            i64: -1,
+            u8: 7,
+            u32: 0xdead_beef,
+            u64: 1,
+            f64: 1.5,
+            unit: (),
+            none: None,
+            some: Some(0x0a0b),
+        })?;
+        #[rustfmt::skip]
+        let expected = [
+            0xfe,
+            0xff, 0xfd,
+            1, 2, 3, 4,
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            7,
+            0xde, 0xad, 0xbe, 0xef,
+            0, 0, 0, 0, 0, 0, 0, 1,
+            0x3f, 0xf8, 0, 0, 0, 0, 0, 0,
+            0x00,
+            0xff, 0x0a, 0x0b,
+        ];
+        assert_eq!(&expected[..], &bytes[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn it_tags_enums() -> Result<()> {
+        assert_eq!(vec![0], round_trip(&Message::Unit)?);
+        assert_eq!(vec![1, 0, 9], round_trip(&Message::Newtype(9))?);
+        assert_eq!(vec![2, 4, 0xff], round_trip(&Message::Tuple(4, true))?);
+        assert_eq!(vec![3, 5], round_trip(&Message::Struct { a: 5 })?);
+        assert_eq!(vec![0, 0], round_trip(&Wide::A)?);
+        assert_eq!(vec![0, 1, 3], round_trip(&Wide::B(3))?);
+        assert!(from_bytes::<Wide>(&[1, 3]).is_err());
+        assert!(from_bytes::<Message>(&[4]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn it_encodes_lists_and_maps_until_the_end() -> Result<()> {
+        let list = vec!["a".to_string(), "bc".to_string()];
+        assert_eq!(
+            vec![0, 0, 0, 1, b'a', 0, 0, 0, 2, b'b', b'c'],
+            round_trip(&list)?
+        );
+        let map = BTreeMap::from([(1u8, true), (2u8, false)]);
+        assert_eq!(vec![1, 0xff, 2, 0], round_trip(&map)?);
+        Ok(())
+    }
+
+    #[test]
+    fn it_fails_instead_of_panicking() {
+        assert!(matches!(
+            to_bytes_no_header(&1.0f32),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(matches!(
+            to_bytes_no_header(&'a'),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(matches!(
+            from_bytes::<serde_json::Value>(&[0]),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(matches!(
+            from_bytes::<Option<u8>>(&[1, 0]),
+            Err(Error::InvalidOptionTag(1))
+        ));
+        assert!(matches!(
+            from_bytes::<String>(&[0, 0, 0, 9, b'a']),
+            Err(Error::UnsufficentBytes)
+        ));
+        assert!(matches!(
+            from_bytes::<Vec<()>>(&[0]),
+            Err(Error::ZeroSizedElement)
+        ));
+        assert!(matches!(
+            from_bytes::<String>(&[0x40, 0, 0, 0, b'a']),
+            Err(Error::SizePrefixOverflow { .. })
+        ));
+        // only the u16 header of `to_bytes` limits values to 64 KiB
+        assert!(matches!(
+            to_bytes(&vec![0u8; 70_000]),
+            Err(Error::SizePrefixOverflow {
+                size: 70_000,
+                max: 65_535
+            })
+        ));
+        let long = "a".repeat(70_000);
+        assert_eq!(70_004, to_bytes_no_header(&long).unwrap().len());
+        assert_eq!(
+            long,
+            from_bytes::<String>(&to_bytes_no_header(&long).unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_borrows_from_the_input() -> Result<()> {
+        let input = [0, 0, 0, 2, b'h', b'i', 1, 2, 3, 4, 5, 6, 7, 8];
+        let borrowed: Borrowed = from_bytes(&input)?;
+        assert_eq!(
+            Borrowed {
+                name: "hi",
+                hash: &[1, 2, 3, 4],
+                copied: [5, 6],
+                rest: &[7, 8],
+            },
+            borrowed
+        );
+        assert!(std::ptr::eq(&input[6..10], borrowed.hash.as_slice()));
+        assert_eq!(&input[..], &to_bytes_no_header(&borrowed)?[..]);
+        assert!(matches!(
+            from_bytes::<Borrowed>(&input[..8]),
+            Err(Error::UnsufficentBytes)
+        ));
+        Ok(())
     }
 }
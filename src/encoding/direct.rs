@@ -0,0 +1,673 @@
+/// Binary encoding without serde, usually implemented with `#[derive(TezosEncoding)]`.
+///
+/// The derive follows the type definition the way octez combinators do: fields are encoded
+/// in order (`obj`, `tup`), enums are prefixed by their tag (`union`), u8 by default or u16
+/// with `#[encoding(tag_size = "u16")]`, and variants pick theirs with `#[encoding(tag = 1)]`.
+/// Fields accept `#[encoding(dynamic)]` (`dynamic_size`, `dynamic = "u8"` for other prefixes),
+/// `#[encoding(bound = 128)]` (`check_size`), `#[encoding(fixed = 32)]` (`Fixed.list`) and
+/// `#[encoding(max_length = 100)]` (`Variable.list ~max_length`).
+/// ```rust
+/// use tzhandhsake::encoding::direct::{from_bytes, to_bytes_no_header, TezosEncoding};
+///
+/// #[derive(Debug, PartialEq, TezosEncoding)]
+/// struct Point {
+///     #[encoding(dynamic = "u8")]
+///     name: Vec<u8>,
+///     x: u16,
+/// }
+///
+/// let point = Point { name: vec![b'a'], x: 2 };
+/// let bytes = to_bytes_no_header(&point).unwrap();
+/// assert_eq!(vec![1, b'a', 0, 2], bytes);
+/// assert_eq!(point, from_bytes(&bytes).unwrap());
+/// ```
+use std::{fmt::Display, net::SocketAddr};
+
+use serde::{Deserialize, Serialize};
+pub use tzhandhsake_derive::TezosEncoding;
+
+use super::zarith::{Natural, Zarith};
+pub use super::{
+    dynamic::SizePrefix,
+    error::{Error, Result},
+    schema::{Case, Field, Schema},
+    span::Span,
+};
+
+pub trait TezosEncoding: Sized {
+    fn encode(&self, out: &mut BinWriter) -> Result<()>;
+    fn decode(input: &mut BinReader<'_>) -> Result<Self>;
+    /// Layout of the encoding, see `encoding::schema`
+    fn schema() -> Schema;
+}
+
+/// Encodes `value` prefixed by its size as a u16, like p2p messages.
+pub fn to_bytes<T: TezosEncoding>(value: &T) -> Result<Vec<u8>> {
+    let mut out = BinWriter::new();
+    out.dynamic(SizePrefix::U16, |out| value.encode(out))?;
+    Ok(out.into_bytes())
+}
+
+pub fn to_bytes_no_header<T: TezosEncoding>(value: &T) -> Result<Vec<u8>> {
+    let mut out = BinWriter::new();
+    value.encode(&mut out)?;
+    Ok(out.into_bytes())
+}
+
+/// Decodes a `T` that must use all of `bytes`.
+pub fn from_bytes<T: TezosEncoding>(bytes: &[u8]) -> Result<T> {
+    let mut input = BinReader::new(bytes);
+    let value = T::decode(&mut input)?;
+    if input.is_empty() {
+        Ok(value)
+    } else {
+        Err(Error::ExtraBytes)
+    }
+}
+
+/// Decodes a `T` like `from_bytes`, along with the spans of everything decoded until it
+/// succeeded or failed, see `encoding::span`.
+pub fn decode_spans<T: TezosEncoding>(bytes: &[u8]) -> (Result<T>, Span) {
+    let mut input = BinReader {
+        input: bytes,
+        offset: 0,
+        spans: Some(vec![]),
+    };
+    let value = T::decode(&mut input).and_then(|value| {
+        if input.is_empty() {
+            Ok(value)
+        } else {
+            Err(Error::ExtraBytes)
+        }
+    });
+    let span = Span {
+        name: String::new(),
+        range: 0..input.offset,
+        children: input.spans.unwrap_or_default(),
+    };
+    (value, span)
+}
+
+/// Annotated hex dump of `bytes` decoded as a `T`, ending with the error if decoding failed.
+/// Meant for failure messages: `assert_eq!(expected, bytes, "{}", annotated::<T>(&bytes))`.
+pub fn annotated<T: TezosEncoding>(bytes: &[u8]) -> String {
+    let (value, span) = decode_spans::<T>(bytes);
+    let mut dump = span.annotate(bytes);
+    if let Err(e) = value {
+        dump.push_str(&format!("error: {}\n", e));
+    }
+    dump
+}
+
+/// Growing buffer values are encoded into
+#[derive(Debug, Default)]
+pub struct BinWriter {
+    bytes: Vec<u8>,
+}
+
+impl BinWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Writes what `f` writes prefixed by its size.
+    pub fn dynamic<F>(&mut self, prefix: SizePrefix, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<()>,
+    {
+        let start = self.bytes.len();
+        self.bytes.resize(start + prefix.bytes(), 0);
+        f(self)?;
+        let size = self.bytes.len() - start - prefix.bytes();
+        if size > prefix.max() {
+            return Err(Error::SizePrefixOverflow {
+                size,
+                max: prefix.max(),
+            });
+        }
+        let be = (size as u32).to_be_bytes();
+        self.bytes[start..start + prefix.bytes()].copy_from_slice(&be[4 - prefix.bytes()..]);
+        Ok(())
+    }
+
+    /// Fails when `f` writes more than `bound` bytes.
+    pub fn bounded<F>(&mut self, bound: usize, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<()>,
+    {
+        let start = self.bytes.len();
+        f(self)?;
+        let size = self.bytes.len() - start;
+        if size > bound {
+            return Err(Error::BoundExceeded { bound, size });
+        }
+        Ok(())
+    }
+}
+
+/// Bytes left to decode, borrowed from the input
+#[derive(Debug, Clone)]
+pub struct BinReader<'a> {
+    input: &'a [u8],
+    /// Position of `input` in the whole input
+    offset: usize,
+    /// Spans of the value being decoded, only when decoding with `decode_spans`
+    spans: Option<Vec<Span>>,
+}
+
+impl<'a> BinReader<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        Self {
+            input,
+            offset: 0,
+            spans: None,
+        }
+    }
+
+    /// Number of bytes read since the beginning of the whole input
+    pub fn position(&self) -> usize {
+        self.offset
+    }
+
+    /// Records what `f` decodes as `name`, when decoding with `decode_spans`.
+    pub fn span<T, F>(&mut self, name: impl Display, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        self.span_from(self.offset, name, f)
+    }
+
+    /// Same as `span`, including the bytes read since `start`, like the tag of a variant.
+    pub fn span_from<T, F>(&mut self, start: usize, name: impl Display, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        let Some(siblings) = self.spans.replace(vec![]) else {
+            return f(self);
+        };
+        // recorded even when `f` fails, to show how far decoding went
+        let value = f(self);
+        let children = self.spans.replace(siblings).unwrap_or_default();
+        self.spans.get_or_insert_with(Vec::new).push(Span {
+            name: name.to_string(),
+            range: start..self.offset,
+            children,
+        });
+        value
+    }
+
+    /// Bytes not decoded yet
+    pub fn as_slice(&self) -> &'a [u8] {
+        self.input
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.input.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    pub fn read(&mut self, n: usize) -> Result<&'a [u8]> {
+        if n > self.input.len() {
+            return Err(Error::UnsufficentBytes);
+        }
+        let (read, rest) = self.input.split_at(n);
+        self.input = rest;
+        self.offset += n;
+        Ok(read)
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(self.read(N)?);
+        Ok(bytes)
+    }
+
+    /// Reads the size prefix and lets `f` decode exactly that many bytes.
+    pub fn dynamic<T, F>(&mut self, prefix: SizePrefix, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut BinReader<'a>) -> Result<T>,
+    {
+        let size = self
+            .span("size", |input| input.read(prefix.bytes()))?
+            .iter()
+            .fold(0usize, |size, b| (size << 8) | *b as usize);
+        if size > prefix.max() {
+            return Err(Error::SizePrefixOverflow {
+                size,
+                max: prefix.max(),
+            });
+        }
+        let offset = self.offset;
+        let mut inner = BinReader {
+            input: self.read(size)?,
+            offset,
+            spans: self.spans.take(),
+        };
+        let value = f(&mut inner);
+        self.spans = inner.spans.take();
+        let value = value?;
+        if !inner.is_empty() {
+            return Err(Error::DynamicSizeMismatch {
+                declared: size,
+                read: size - inner.remaining(),
+            });
+        }
+        Ok(value)
+    }
+
+    /// Fails when `f` reads more than `bound` bytes.
+    pub fn bounded<T, F>(&mut self, bound: usize, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        let before = self.remaining();
+        let value = f(self)?;
+        let size = before - self.remaining();
+        if size > bound {
+            return Err(Error::BoundExceeded { bound, size });
+        }
+        Ok(value)
+    }
+}
+
+/// Encodes exactly `len` values without prefix, octez `Fixed.list`.
+pub fn encode_fixed<T: TezosEncoding>(values: &[T], len: usize, out: &mut BinWriter) -> Result<()> {
+    if values.len() != len {
+        return Err(Error::FixedLengthMismatch {
+            expected: len,
+            actual: values.len(),
+        });
+    }
+    values.iter().try_for_each(|value| value.encode(out))
+}
+
+pub fn decode_fixed<T: TezosEncoding>(input: &mut BinReader<'_>, len: usize) -> Result<Vec<T>> {
+    (0..len).map(|i| input.span(i, T::decode)).collect()
+}
+
+/// Encodes at most `max` values until the end of the input, octez `Variable.list ~max_length`.
+pub fn encode_max_length<T: TezosEncoding>(
+    values: &[T],
+    max: usize,
+    out: &mut BinWriter,
+) -> Result<()> {
+    if values.len() > max {
+        return Err(Error::ListTooLong { max });
+    }
+    values.iter().try_for_each(|value| value.encode(out))
+}
+
+pub fn decode_max_length<T: TezosEncoding>(
+    input: &mut BinReader<'_>,
+    max: usize,
+) -> Result<Vec<T>> {
+    decode_until_end(input, Some(max))
+}
+
+/// Elements until the end of `input`, failing on an element without bytes that would
+/// otherwise be decoded forever.
+fn decode_until_end<T: TezosEncoding>(
+    input: &mut BinReader<'_>,
+    max: Option<usize>,
+) -> Result<Vec<T>> {
+    let mut values = vec![];
+    while !input.is_empty() {
+        if let Some(max) = max.filter(|max| values.len() == *max) {
+            return Err(Error::ListTooLong { max });
+        }
+        let before = input.remaining();
+        values.push(input.span(values.len(), T::decode)?);
+        if input.remaining() == before {
+            return Err(Error::ZeroSizedElement);
+        }
+    }
+    Ok(values)
+}
+
+macro_rules! numbers {
+    ($($int:ty => $schema:expr),*) => {
+        $(
+            impl TezosEncoding for $int {
+                fn encode(&self, out: &mut BinWriter) -> Result<()> {
+                    out.write(&self.to_be_bytes());
+                    Ok(())
+                }
+
+                fn decode(input: &mut BinReader<'_>) -> Result<Self> {
+                    input.read_array().map(<$int>::from_be_bytes)
+                }
+
+                fn schema() -> Schema {
+                    $schema
+                }
+            }
+        )*
+    };
+}
+
+numbers!(
+    u8 => Schema::Int { signed: false, bits: 8 },
+    i8 => Schema::Int { signed: true, bits: 8 },
+    u16 => Schema::Int { signed: false, bits: 16 },
+    i16 => Schema::Int { signed: true, bits: 16 },
+    u32 => Schema::Int { signed: false, bits: 32 },
+    i32 => Schema::Int { signed: true, bits: 32 },
+    u64 => Schema::Int { signed: false, bits: 64 },
+    i64 => Schema::Int { signed: true, bits: 64 },
+    f64 => Schema::Float
+);
+
+impl TezosEncoding for bool {
+    fn encode(&self, out: &mut BinWriter) -> Result<()> {
+        out.write(&[if *self { 0xff } else { 0x00 }]);
+        Ok(())
+    }
+
+    fn decode(input: &mut BinReader<'_>) -> Result<Self> {
+        match u8::decode(input)? {
+            0xff => Ok(true),
+            0x00 => Ok(false),
+            b => Err(Error::InvalidBool(b)),
+        }
+    }
+
+    fn schema() -> Schema {
+        Schema::Bool
+    }
+}
+
+/// Prefixed by its size as a u30, octez `string`
+impl TezosEncoding for String {
+    fn encode(&self, out: &mut BinWriter) -> Result<()> {
+        out.dynamic(SizePrefix::U30, |out| {
+            out.write(self.as_bytes());
+            Ok(())
+        })
+    }
+
+    fn decode(input: &mut BinReader<'_>) -> Result<Self> {
+        input.dynamic(SizePrefix::U30, |input| {
+            let bytes = input.read(input.remaining())?;
+            Ok(String::from_utf8(bytes.to_vec())?)
+        })
+    }
+
+    fn schema() -> Schema {
+        string_schema()
+    }
+}
+
+fn string_schema() -> Schema {
+    Schema::Dynamic {
+        prefix: SizePrefix::U30,
+        value: Box::new(Schema::String),
+    }
+}
+
+/// Elements until the end of the input, octez `Variable.list`. Lists anywhere else need
+/// a `dynamic` prefix.
+impl<T: TezosEncoding> TezosEncoding for Vec<T> {
+    fn encode(&self, out: &mut BinWriter) -> Result<()> {
+        self.iter().try_for_each(|value| value.encode(out))
+    }
+
+    fn decode(input: &mut BinReader<'_>) -> Result<Self> {
+        decode_until_end(input, None)
+    }
+
+    fn schema() -> Schema {
+        Schema::List {
+            length: None,
+            max_length: None,
+            elements: Box::new(T::schema()),
+        }
+    }
+}
+
+impl<T: TezosEncoding> TezosEncoding for Option<T> {
+    fn encode(&self, out: &mut BinWriter) -> Result<()> {
+        match self {
+            None => 0x00u8.encode(out),
+            Some(value) => {
+                0xffu8.encode(out)?;
+                value.encode(out)
+            }
+        }
+    }
+
+    fn decode(input: &mut BinReader<'_>) -> Result<Self> {
+        match u8::decode(input)? {
+            0x00 => Ok(None),
+            0xff => T::decode(input).map(Some),
+            tag => Err(Error::InvalidOptionTag(tag)),
+        }
+    }
+
+    fn schema() -> Schema {
+        Schema::Option {
+            value: Box::new(T::schema()),
+        }
+    }
+}
+
+/// Bytes until the end of the input, octez `Variable.bytes`, hex in json.
+/// Unlike `Vec<u8>` they are read at once, and annotated as a single value.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Bytes(#[serde(with = "super::json::bytes")] pub Vec<u8>);
+
+impl From<Vec<u8>> for Bytes {
+    fn from(value: Vec<u8>) -> Self {
+        Self(value)
+    }
+}
+
+impl AsRef<[u8]> for Bytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl TezosEncoding for Bytes {
+    fn encode(&self, out: &mut BinWriter) -> Result<()> {
+        out.write(&self.0);
+        Ok(())
+    }
+
+    fn decode(input: &mut BinReader<'_>) -> Result<Self> {
+        Ok(Self(input.read(input.remaining())?.to_vec()))
+    }
+
+    fn schema() -> Schema {
+        Schema::Bytes { size: None }
+    }
+}
+
+impl<const N: usize> TezosEncoding for [u8; N] {
+    fn encode(&self, out: &mut BinWriter) -> Result<()> {
+        out.write(self);
+        Ok(())
+    }
+
+    fn decode(input: &mut BinReader<'_>) -> Result<Self> {
+        input.read_array()
+    }
+
+    fn schema() -> Schema {
+        Schema::Bytes { size: Some(N) }
+    }
+}
+
+/// "addr:port" string, octez `P2p_point.Id.encoding`
+impl TezosEncoding for SocketAddr {
+    fn encode(&self, out: &mut BinWriter) -> Result<()> {
+        self.to_string().encode(out)
+    }
+
+    fn decode(input: &mut BinReader<'_>) -> Result<Self> {
+        let point = String::decode(input)?;
+        point
+            .parse()
+            .map_err(|_| Error::Custom(format!("invalid point `{}`, expected addr:port", point)))
+    }
+
+    fn schema() -> Schema {
+        string_schema()
+    }
+}
+
+macro_rules! zarith {
+    ($($name:ty => $signed:expr),*) => {
+        $(
+            impl TezosEncoding for $name {
+                fn encode(&self, out: &mut BinWriter) -> Result<()> {
+                    out.write(&self.to_bytes());
+                    Ok(())
+                }
+
+                fn decode(input: &mut BinReader<'_>) -> Result<Self> {
+                    let (value, used) = <$name>::from_bytes(input.as_slice())?;
+                    input.read(used)?;
+                    Ok(value)
+                }
+
+                fn schema() -> Schema {
+                    Schema::Zarith { signed: $signed }
+                }
+            }
+        )*
+    };
+}
+
+zarith!(Natural => false, Zarith => true);
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::encoding::{bin, dynamic::Dynamic8, error::Error, zarith::Zarith};
+
+    use super::{annotated, from_bytes, to_bytes, to_bytes_no_header, TezosEncoding};
+
+    #[derive(Debug, PartialEq, TezosEncoding)]
+    struct Block {
+        level: i32,
+        #[encoding(fixed = 2)]
+        hashes: Vec<[u8; 2]>,
+        #[encoding(dynamic, bound = 8)]
+        name: Vec<u8>,
+        fee: Option<Zarith>,
+        operations: Vec<Operation>,
+    }
+
+    #[derive(Debug, PartialEq, TezosEncoding)]
+    #[encoding(tag_size = "u16")]
+    enum Operation {
+        Noop,
+        #[encoding(tag = 0x100)]
+        Transfer(u8, #[encoding(dynamic = "u8")] Vec<u16>),
+    }
+
+    #[derive(Debug, PartialEq, TezosEncoding)]
+    struct Nothing;
+
+    #[test]
+    fn it_encodes_derived_types() -> Result<()> {
+        let block = Block {
+            level: -1,
+            hashes: vec![[1, 2], [3, 4]],
+            name: b"abc".to_vec(),
+            fee: Some(Zarith::from(64)),
+            operations: vec![Operation::Transfer(7, vec![8]), Operation::Noop],
+        };
+        let bytes = to_bytes_no_header(&block)?;
+        #[rustfmt::skip]
+        assert_eq!(
+            vec![
+                0xff, 0xff, 0xff, 0xff,
+                1, 2, 3, 4,
+                0, 0, 0, 3, b'a', b'b', b'c',
+                0xff, 0x80, 0x01,
+                1, 0, 7, 2, 0, 8,
+                0, 0,
+            ],
+            bytes,
+            "{}",
+            annotated::<Block>(&bytes)
+        );
+        assert_eq!(block, from_bytes(&bytes)?);
+        assert_eq!(&[0, 26], &to_bytes(&block)?[..2]);
+        Ok(())
+    }
+
+    #[test]
+    fn it_agrees_with_serde() -> Result<()> {
+        let value = (String::from("tezos"), Some(true), Dynamic8(vec![1u8, 2]));
+        let serde = bin::to_bytes_no_header(&value)?;
+        let mut direct = to_bytes_no_header(&value.0)?;
+        direct.extend(to_bytes_no_header(&value.1)?);
+        direct.extend([2, 1, 2]);
+        assert_eq!(serde, direct);
+        assert_eq!(value.0, bin::from_bytes::<String>(&serde[..9])?);
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_invalid_input() {
+        let mut block = Block {
+            level: 0,
+            hashes: vec![],
+            name: vec![],
+            fee: None,
+            operations: vec![],
+        };
+        assert!(matches!(
+            to_bytes_no_header(&block),
+            Err(Error::FixedLengthMismatch {
+                expected: 2,
+                actual: 0
+            })
+        ));
+        block.hashes = vec![[0; 2]; 2];
+        block.name = vec![0; 9];
+        assert!(matches!(
+            to_bytes_no_header(&block),
+            Err(Error::BoundExceeded { bound: 8, size: 9 })
+        ));
+        assert!(matches!(
+            from_bytes::<Operation>(&[0, 2]),
+            Err(Error::UnknownTag {
+                name: "Operation",
+                tag: 2
+            })
+        ));
+        assert!(matches!(
+            from_bytes::<u16>(&[0]),
+            Err(Error::UnsufficentBytes)
+        ));
+        assert!(matches!(from_bytes::<u8>(&[0, 0]), Err(Error::ExtraBytes)));
+        // a list of values without bytes would never reach the end of the input
+        assert!(matches!(
+            from_bytes::<Vec<Nothing>>(&[0]),
+            Err(Error::ZeroSizedElement)
+        ));
+    }
+}
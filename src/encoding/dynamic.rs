@@ -0,0 +1,350 @@
+/// Length prefixed and bounded values, octez `dynamic_size` and `Bounded` encodings.
+///
+/// The wrappers are serialized as tuple structs with magic names that `encoding::bin`
+/// recognizes, they are transparent in json. Fields can use them through the `with`
+/// attribute as well: `#[serde(with = "dynamic::u8")]`.
+/// Lists have no length prefix on their own, a `Dynamic<Vec<T>>` is octez `list` while a
+/// trailing `Vec<T>` is octez `Variable.list`. They implement `TezosEncoding` the same way.
+use std::{fmt, marker::PhantomData};
+
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    ser::SerializeTupleStruct,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use super::{
+    direct::{BinReader, BinWriter, TezosEncoding},
+    error::Result as EncodingResult,
+    schema::Schema,
+};
+
+/// Size of the length prefix of a dynamic value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SizePrefix {
+    U8,
+    U16,
+    /// default of octez, a u32 that must not exceed 2^30 - 1
+    U30,
+    U32,
+}
+
+impl SizePrefix {
+    pub(crate) const fn name(&self) -> &'static str {
+        match self {
+            SizePrefix::U8 => "tezos_dynamic_u8",
+            SizePrefix::U16 => "tezos_dynamic_u16",
+            SizePrefix::U30 => "tezos_dynamic_u30",
+            SizePrefix::U32 => "tezos_dynamic_u32",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        [Self::U8, Self::U16, Self::U30, Self::U32]
+            .into_iter()
+            .find(|prefix| prefix.name() == name)
+    }
+
+    /// Number of bytes of the prefix
+    pub fn bytes(&self) -> usize {
+        match self {
+            SizePrefix::U8 => 1,
+            SizePrefix::U16 => 2,
+            SizePrefix::U30 | SizePrefix::U32 => 4,
+        }
+    }
+
+    /// Largest size the prefix can hold
+    pub fn max(&self) -> usize {
+        match self {
+            SizePrefix::U8 => u8::MAX as usize,
+            SizePrefix::U16 => u16::MAX as usize,
+            SizePrefix::U30 => (1 << 30) - 1,
+            SizePrefix::U32 => u32::MAX as usize,
+        }
+    }
+}
+
+/// Magic name of `Bounded`, the tuple struct length is the bound.
+pub(crate) const BOUNDED: &str = "tezos_bounded";
+
+fn serialize_wrapped<T, S>(
+    name: &'static str,
+    len: usize,
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    T: ?Sized + Serialize,
+    S: Serializer,
+{
+    if serializer.is_human_readable() {
+        value.serialize(serializer)
+    } else {
+        let mut wrapper = serializer.serialize_tuple_struct(name, len)?;
+        wrapper.serialize_field(value)?;
+        wrapper.end()
+    }
+}
+
+fn deserialize_wrapped<'de, T, D>(
+    name: &'static str,
+    len: usize,
+    deserializer: D,
+) -> Result<T, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    if deserializer.is_human_readable() {
+        T::deserialize(deserializer)
+    } else {
+        deserializer.deserialize_tuple_struct(name, len, WrappedVisitor(PhantomData))
+    }
+}
+
+struct WrappedVisitor<T>(PhantomData<T>);
+
+impl<'de, T> Visitor<'de> for WrappedVisitor<T>
+where
+    T: Deserialize<'de>,
+{
+    type Value = T;
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a wrapped value")
+    }
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        seq.next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))
+    }
+}
+
+/// Defines a wrapper prefixing its value with its size, and the matching `with` module.
+macro_rules! dynamic {
+    ($(#[$meta:meta])* $name:ident, $module:ident, $prefix:expr) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Default)]
+        pub struct $name<T>(pub T);
+
+        impl<T> From<T> for $name<T> {
+            fn from(value: T) -> Self {
+                Self(value)
+            }
+        }
+
+        impl<T: Serialize> Serialize for $name<T> {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                $module::serialize(&self.0, serializer)
+            }
+        }
+
+        impl<'de, T: Deserialize<'de>> Deserialize<'de> for $name<T> {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                $module::deserialize(deserializer).map(Self)
+            }
+        }
+
+        impl<T: TezosEncoding> TezosEncoding for $name<T> {
+            fn encode(&self, out: &mut BinWriter) -> EncodingResult<()> {
+                out.dynamic($prefix, |out| self.0.encode(out))
+            }
+
+            fn decode(input: &mut BinReader<'_>) -> EncodingResult<Self> {
+                input.dynamic($prefix, T::decode).map(Self)
+            }
+
+            fn schema() -> Schema {
+                Schema::Dynamic {
+                    prefix: $prefix,
+                    value: Box::new(T::schema()),
+                }
+            }
+        }
+
+        pub mod $module {
+            use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+            use super::SizePrefix;
+
+            pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                T: ?Sized + Serialize,
+                S: Serializer,
+            {
+                super::serialize_wrapped($prefix.name(), 1, value, serializer)
+            }
+
+            pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+            where
+                T: Deserialize<'de>,
+                D: Deserializer<'de>,
+            {
+                super::deserialize_wrapped($prefix.name(), 1, deserializer)
+            }
+        }
+    };
+}
+
+dynamic!(
+    /// Value prefixed by its size as a u30, octez `dynamic_size`
+    Dynamic,
+    u30,
+    SizePrefix::U30
+);
+dynamic!(
+    /// Value prefixed by its size as a u8, octez `dynamic_size ~kind:`Uint8`
+    Dynamic8,
+    u8,
+    SizePrefix::U8
+);
+dynamic!(
+    /// Value prefixed by its size as a u16, octez `dynamic_size ~kind:`Uint16`
+    Dynamic16,
+    u16,
+    SizePrefix::U16
+);
+dynamic!(
+    /// Value prefixed by its size as a full u32
+    Dynamic32,
+    u32,
+    SizePrefix::U32
+);
+
+/// Value whose binary encoding, including its own prefixes, doesn't exceed `MAX` bytes
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Bounded<T, const MAX: usize>(pub T);
+
+impl<T, const MAX: usize> From<T> for Bounded<T, MAX> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T: Serialize, const MAX: usize> Serialize for Bounded<T, MAX> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_wrapped(BOUNDED, MAX, &self.0, serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>, const MAX: usize> Deserialize<'de> for Bounded<T, MAX> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_wrapped(BOUNDED, MAX, deserializer).map(Self)
+    }
+}
+
+impl<T: TezosEncoding, const MAX: usize> TezosEncoding for Bounded<T, MAX> {
+    fn encode(&self, out: &mut BinWriter) -> EncodingResult<()> {
+        out.bounded(MAX, |out| self.0.encode(out))
+    }
+
+    fn decode(input: &mut BinReader<'_>) -> EncodingResult<Self> {
+        input.bounded(MAX, T::decode).map(Self)
+    }
+
+    fn schema() -> Schema {
+        Schema::Bounded {
+            bound: MAX,
+            value: Box::new(T::schema()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use serde::{Deserialize, Serialize};
+
+    use crate::encoding::{
+        bin::{from_bytes, to_bytes_no_header},
+        error::Error,
+    };
+
+    use super::{Bounded, Dynamic, Dynamic16, Dynamic8};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Message {
+        #[serde(with = "super::u8")]
+        small: Vec<u8>,
+        list: Dynamic16<Vec<u16>>,
+        name: Bounded<String, 8>,
+        trailing: Vec<u8>,
+    }
+
+    #[test]
+    fn it_prefixes_dynamic_values() -> Result<()> {
+        let message = Message {
+            small: vec![1, 2],
+            list: Dynamic16(vec![3, 4]),
+            name: Bounded("abc".to_string()),
+            trailing: vec![5, 6],
+        };
+        let bytes = to_bytes_no_header(&message)?;
+        #[rustfmt::skip]
+        assert_eq!(
+            vec![
+                2, 1, 2,
+                0, 4, 0, 3, 0, 4,
+                0, 0, 0, 3, b'a', b'b', b'c',
+                5, 6,
+            ],
+            bytes
+        );
+        assert_eq!(message, from_bytes(&bytes)?);
+        assert_eq!(
+            r#"{"small":[1,2],"list":[3,4],"name":"abc","trailing":[5,6]}"#,
+            serde_json::to_string(&message)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_checks_sizes_and_bounds() {
+        assert!(matches!(
+            to_bytes_no_header(&Dynamic8(vec![0u8; 256])),
+            Err(Error::SizePrefixOverflow {
+                size: 256,
+                max: 255
+            })
+        ));
+        assert!(matches!(
+            to_bytes_no_header(&Bounded::<String, 8>("abcde".into())),
+            Err(Error::BoundExceeded { bound: 8, size: 9 })
+        ));
+        assert!(matches!(
+            from_bytes::<Bounded<String, 8>>(&[0, 0, 0, 5, 1, 2, 3, 4, 5]),
+            Err(Error::BoundExceeded { bound: 8, size: 9 })
+        ));
+        // the declared size must match what the value uses
+        assert!(matches!(
+            from_bytes::<Dynamic<u8>>(&[0, 0, 0, 2, 1, 2]),
+            Err(Error::DynamicSizeMismatch {
+                declared: 2,
+                read: 1
+            })
+        ));
+        assert!(matches!(
+            from_bytes::<Dynamic<Vec<u8>>>(&[0, 0, 0, 3, 1, 2]),
+            Err(Error::UnsufficentBytes)
+        ));
+        assert!(matches!(
+            from_bytes::<Dynamic<Vec<u8>>>(&[0x40, 0, 0, 0]),
+            Err(Error::SizePrefixOverflow { .. })
+        ));
+    }
+}
@@ -2,20 +2,59 @@ use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("String longer that u16")]
-    StringTooLong,
     #[error("Custom({0})")]
     Custom(String),
-    #[error("Maximum size reached: was {before}, added: {added}")]
-    SizeOverflow { before: u16, added: u16 },
     #[error("IO error: `{0}`")]
     IO(#[from] std::io::Error),
     #[error("UTF decoding error: `{0}`")]
     FormUTF8(#[from] std::string::FromUtf8Error),
+    #[error("UTF decoding error: `{0}`")]
+    Utf8(#[from] std::str::Utf8Error),
     #[error("Too many bytes")]
     ExtraBytes,
     #[error("Not enough bytes")]
     UnsufficentBytes,
+    #[error("Invalid boolean {0:#04x}, expected 0x00 or 0xff")]
+    InvalidBool(u8),
+    #[error("Invalid option tag {0:#04x}, expected 0x00 or 0xff")]
+    InvalidOptionTag(u8),
+    #[error("Variant index {0} doesn't fit in the tag")]
+    TagOverflow(u32),
+    #[error("Unsupported by the binary encoding: {0}")]
+    Unsupported(&'static str),
+    #[error("Size {size} doesn't fit in the size prefix, at most {max}")]
+    SizePrefixOverflow { size: usize, max: usize },
+    #[error("Encoded size {size} exceeds the bound {bound}")]
+    BoundExceeded { bound: usize, size: usize },
+    #[error("Dynamic size of {declared} bytes but the value used {read}")]
+    DynamicSizeMismatch { declared: usize, read: usize },
+    #[error("Invalid zarith number, trailing zero")]
+    InvalidZarith,
+    #[error("{value} doesn't fit in {target}")]
+    NumberOverflow { value: String, target: &'static str },
+    #[error("Expected {expected} elements, got {actual}")]
+    FixedLengthMismatch { expected: usize, actual: usize },
+    #[error("List element decoded from no bytes, the list would never end")]
+    ZeroSizedElement,
+    #[error("List of more than {max} elements")]
+    ListTooLong { max: usize },
+    #[error("Unknown tag {tag} for {name}")]
+    UnknownTag { name: &'static str, tag: u16 },
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum B58Error {
+    #[error("Invalid base58: `{0}`")]
+    InvalidBase58(String),
+    #[error("Invalid base58 checksum")]
+    InvalidChecksum,
+    #[error("Expected a value starting with `{expected}`, got `{value}`")]
+    UnexpectedPrefix {
+        expected: &'static str,
+        value: String,
+    },
+    #[error("Expected a payload of {expected} bytes, got {actual}")]
+    InvalidLength { expected: usize, actual: usize },
 }
 
 impl serde::ser::Error for Error {
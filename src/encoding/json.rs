@@ -1,11 +1,19 @@
-/// Json serialization for configurations and identities
+/// Json serialization for configurations, identities and p2p messages.
+///
+/// Messages follow octez `Data_encoding.Json`: bytes are lower case hex, hashes are b58check
+/// (see `b58`), timestamps are RFC3339 and zarith numbers decimal strings since they don't fit
+/// in a javascript number. The helpers below only change the json, binary encodings are untouched.
 use serde::{
     de::{self},
-    ser, Deserialize, Serialize,
+    ser, Deserialize, Deserializer, Serialize, Serializer,
 };
 
+use crypto_box::{PublicKey, SecretKey};
+
 use crate::identity::JsonNonce;
 
+use super::bin::deserialize_fixed;
+
 impl<'de> Deserialize<'de> for JsonNonce {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -22,16 +30,136 @@ impl Serialize for JsonNonce {
     where
         S: ser::Serializer,
     {
-        serdect::array::serialize_hex_upper_or_bin(&self, serializer)
+        serdect::array::serialize_hex_lower_or_bin(&self, serializer)
+    }
+}
+
+/// octez writes keys as lower case hex, unlike `crypto_box`
+pub fn serialize_public_key<S>(pk: &PublicKey, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: ser::Serializer,
+{
+    serdect::array::serialize_hex_lower_or_bin(pk.as_bytes(), serializer)
+}
+
+pub fn serialize_secret_key<S>(sk: &SecretKey, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: ser::Serializer,
+{
+    serdect::array::serialize_hex_lower_or_bin(&sk.to_bytes(), serializer)
+}
+
+/// Fixed size bytes, hex in json
+pub fn serialize_hex<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&hex::encode(bytes))
+    } else {
+        serializer.serialize_bytes(bytes)
+    }
+}
+
+pub fn deserialize_hex<'de, D, const N: usize>(deserializer: D) -> Result<[u8; N], D::Error>
+where
+    D: Deserializer<'de>,
+{
+    if !deserializer.is_human_readable() {
+        return deserialize_fixed(deserializer);
+    }
+    let mut bytes = [0; N];
+    let hex = String::deserialize(deserializer)?;
+    hex::decode_to_slice(&hex, &mut bytes).map_err(|_| {
+        de::Error::invalid_value(
+            de::Unexpected::Str(&hex),
+            &format!("{} bytes in hex", N).as_str(),
+        )
+    })?;
+    Ok(bytes)
+}
+
+/// `Vec<u8>` fields, hex in json: `#[serde(with = "json::bytes")]`
+pub mod bytes {
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S>(bytes: &Vec<u8>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&hex::encode(bytes))
+        } else {
+            bytes.serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            let hex = String::deserialize(deserializer)?;
+            hex::decode(&hex)
+                .map_err(|_| de::Error::invalid_value(de::Unexpected::Str(&hex), &"hex bytes"))
+        } else {
+            Vec::deserialize(deserializer)
+        }
+    }
+}
+
+/// Seconds since the epoch, octez `Time.Protocol.encoding`: RFC3339 in json, decimal strings
+/// are accepted as well and used for dates out of the RFC3339 range:
+/// `#[serde(with = "json::timestamp")]`
+pub mod timestamp {
+    use chrono::{DateTime, SecondsFormat};
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(value: &i64, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if !serializer.is_human_readable() {
+            return serializer.serialize_i64(*value);
+        }
+        match DateTime::from_timestamp(*value, 0) {
+            Some(date) => {
+                serializer.serialize_str(&date.to_rfc3339_opts(SecondsFormat::Secs, true))
+            }
+            None => serializer.collect_str(value),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<i64, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if !deserializer.is_human_readable() {
+            return i64::deserialize(deserializer);
+        }
+        let value = String::deserialize(deserializer)?;
+        value
+            .parse()
+            .or_else(|_| DateTime::parse_from_rfc3339(&value).map(|date| date.timestamp()))
+            .map_err(|_| {
+                de::Error::invalid_value(
+                    de::Unexpected::Str(&value),
+                    &"an RFC3339 date or an int64",
+                )
+            })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use serde::Deserialize;
+    use serde::{Deserialize, Serialize};
     use serde_json::json;
 
-    use crate::identity::Identity;
+    use crate::{
+        encoding::{bin, direct},
+        identity::Identity,
+        p2p::{AckMessage, ConnectionMessage, Metadata},
+    };
 
     #[test]
     fn it_deserializes_peer_id_json() {
@@ -44,7 +172,115 @@ mod tests {
         let peer_id = Identity::deserialize(data).expect("should deserialize");
         assert_eq!(
             "idrpbo9Ru5pYiWTg1i2VPABG6Catfm".to_string(),
-            peer_id.peer_id
+            peer_id.peer_id.to_string()
         )
     }
+
+    #[test]
+    fn it_serializes_identity_like_octez() {
+        let data = json!({
+            "peer_id": "idrpbo9Ru5pYiWTg1i2VPABG6Catfm",
+            "public_key": "3b2c3950d9c59a5c19af7be39ce5844523bc002651cd45417e635462ce666f07",
+            "secret_key": "d0c24b1537d8651ebc39951030c1fccea83188d563ac8cf9667f7ccb7765b1ba",
+            "proof_of_work_stamp": "ea2fa50b542755be6bc4a53188d758cf4e7d4e085082f4bd"
+        });
+        let identity = Identity::deserialize(&data).expect("should deserialize");
+        assert_eq!(
+            data,
+            serde_json::to_value(&identity).expect("should serialize")
+        );
+    }
+
+    #[test]
+    fn it_writes_p2p_messages_like_octez() {
+        let message = json!({
+            "port": 9732,
+            "pubkey": "3b2c3950d9c59a5c19af7be39ce5844523bc002651cd45417e635462ce666f07",
+            "proof_of_work_stamp": "ea2fa50b542755be6bc4a53188d758cf4e7d4e085082f4bd",
+            "message_nonce": "000000000000000000000000000000000000000000000001",
+            "version": {
+                "chain_name": "TEZOS_MAINNET",
+                "distributed_db_version": 2,
+                "p2p_version": 1
+            }
+        });
+        let decoded: ConnectionMessage = serde_json::from_value(message.clone()).unwrap();
+        assert_eq!(9732, decoded.port);
+        assert_eq!(1, decoded.nonce.as_ref()[23]);
+        assert_eq!(message, serde_json::to_value(&decoded).unwrap());
+        // json doesn't change the binary encoding
+        let bytes = direct::to_bytes_no_header(&decoded).unwrap();
+        assert_eq!(&[0x3b, 0x2c], &bytes[2..4]);
+        assert_eq!(decoded, direct::from_bytes(&bytes).unwrap());
+
+        let metadata = Metadata {
+            disable_mempool: true,
+            private_node: false,
+        };
+        assert_eq!(
+            json!({"disable_mempool": true, "private_node": false}),
+            serde_json::to_value(metadata).unwrap()
+        );
+        assert_eq!(
+            json!({"ack": {}}),
+            serde_json::to_value(AckMessage::Ack).unwrap()
+        );
+        assert_eq!(
+            AckMessage::NackV0,
+            serde_json::from_value(json!({"nack_v_0": {}})).unwrap()
+        );
+        assert!(serde_json::from_value::<ConnectionMessage>(json!({"pubkey": "3b2c"})).is_err());
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Fixture {
+        /// until the end of the input in binary
+        #[serde(with = "super::bytes")]
+        data: Vec<u8>,
+    }
+
+    #[test]
+    fn it_writes_bytes_as_hex() {
+        let fixture = Fixture {
+            data: vec![0xca, 0xfe],
+        };
+        let json = json!({"data": "cafe"});
+        assert_eq!(json, serde_json::to_value(&fixture).unwrap());
+        assert_eq!(fixture, serde_json::from_value(json).unwrap());
+        assert!(serde_json::from_value::<Fixture>(json!({"data": "caf"})).is_err());
+        assert!(serde_json::from_value::<Fixture>(json!({"data": [0xca, 0xfe]})).is_err());
+
+        let bytes = bin::to_bytes_no_header(&fixture).unwrap();
+        assert_eq!(fixture, bin::from_bytes(&bytes).unwrap());
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Timestamp(#[serde(with = "super::timestamp")] i64);
+
+    #[test]
+    fn it_writes_timestamps_as_rfc3339() {
+        let date = json!("2023-11-14T22:13:20Z");
+        assert_eq!(
+            date,
+            serde_json::to_value(Timestamp(1_700_000_000)).unwrap()
+        );
+        assert_eq!(
+            Timestamp(1_700_000_000),
+            serde_json::from_value(date).unwrap()
+        );
+        // like octez, decimal seconds are accepted, and used out of the RFC3339 range
+        assert_eq!(
+            Timestamp(1_700_000_000),
+            serde_json::from_value(json!("1700000000")).unwrap()
+        );
+        assert_eq!(
+            Timestamp(1_700_000_000),
+            serde_json::from_value(json!("2023-11-14T23:13:20+01:00")).unwrap()
+        );
+        assert_eq!(
+            json!("-9223372036854775808"),
+            serde_json::to_value(Timestamp(i64::MIN)).unwrap()
+        );
+        assert!(serde_json::from_value::<Timestamp>(json!("yesterday")).is_err());
+    }
 }
@@ -1,4 +1,9 @@
+pub mod b58;
 pub mod bin;
+pub mod direct;
+pub mod dynamic;
 pub mod error;
 pub mod json;
-pub mod read;
+pub mod schema;
+pub mod span;
+pub mod zarith;
@@ -0,0 +1,453 @@
+/// Description of binary encodings, what `octez-codec describe` prints.
+///
+/// Every `TezosEncoding` type returns its `Schema`, derived types list their fields and cases
+/// under their Rust names. [`Schema::describe`] renders the tables octez prints, the json form
+/// is the `Schema` serialized.
+use std::collections::HashSet;
+
+use serde::Serialize;
+
+use super::dynamic::SizePrefix;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Schema {
+    Bool,
+    Int {
+        signed: bool,
+        bits: u8,
+    },
+    Float,
+    /// Arbitrary precision, octez `n` and `z`
+    Zarith {
+        signed: bool,
+    },
+    /// `size` bytes, or the rest of the input
+    Bytes {
+        size: Option<usize>,
+    },
+    /// Utf8 bytes until the end of the input, usually in a `Dynamic`
+    String,
+    Option {
+        value: Box<Schema>,
+    },
+    /// `length` elements, or elements until the end of the input
+    List {
+        length: Option<usize>,
+        /// At most `max_length` elements, when their number isn't fixed
+        max_length: Option<usize>,
+        elements: Box<Schema>,
+    },
+    Dynamic {
+        prefix: SizePrefix,
+        value: Box<Schema>,
+    },
+    Bounded {
+        bound: usize,
+        value: Box<Schema>,
+    },
+    Obj {
+        name: &'static str,
+        fields: Vec<Field>,
+    },
+    Union {
+        name: &'static str,
+        /// in bytes
+        tag_size: u8,
+        cases: Vec<Case>,
+    },
+    /// The enclosing obj or union `name`, in recursive encodings (octez `mu`)
+    Ref {
+        name: &'static str,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Field {
+    /// None for the fields of tuples
+    pub name: Option<&'static str>,
+    pub schema: Schema,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Case {
+    pub tag: u16,
+    pub name: &'static str,
+    pub fields: Vec<Field>,
+}
+
+impl Schema {
+    /// `length` elements of a list, used by `#[encoding(fixed = ...)]`
+    pub fn fixed(self, length: usize) -> Self {
+        match self {
+            Schema::List { elements, .. } => Schema::List {
+                length: Some(length),
+                max_length: None,
+                elements,
+            },
+            schema => schema,
+        }
+    }
+
+    /// At most `max` elements of a list, used by `#[encoding(max_length = ...)]`
+    pub fn max_length(self, max: usize) -> Self {
+        match self {
+            Schema::List { elements, .. } => Schema::List {
+                length: None,
+                max_length: Some(max),
+                elements,
+            },
+            schema => schema,
+        }
+    }
+
+    /// Size in bytes when it doesn't depend on the value
+    pub fn size(&self) -> Option<usize> {
+        match self {
+            Schema::Bool => Some(1),
+            Schema::Int { bits, .. } => Some(*bits as usize / 8),
+            Schema::Float => Some(8),
+            Schema::Bytes { size } => *size,
+            Schema::List {
+                length: Some(length),
+                elements,
+                ..
+            } => elements.size().map(|size| size * length),
+            Schema::Bounded { value, .. } => value.size(),
+            Schema::Obj { fields, .. } => fields_size(fields),
+            Schema::Union {
+                tag_size, cases, ..
+            } => {
+                let mut sizes = cases.iter().map(|case| fields_size(&case.fields));
+                let first = sizes.next()??;
+                sizes
+                    .all(|size| size == Some(first))
+                    .then_some(*tag_size as usize + first)
+            }
+            Schema::Zarith { .. }
+            | Schema::String
+            | Schema::Option { .. }
+            | Schema::List { length: None, .. }
+            | Schema::Dynamic { .. }
+            | Schema::Ref { .. } => None,
+        }
+    }
+
+    /// Tables of the encoding and of the objects it contains, like `octez-codec describe`.
+    pub fn describe(&self, name: &str) -> String {
+        let mut out = String::new();
+        let mut sections = vec![(name.to_string(), self)];
+        let mut seen = HashSet::new();
+        // elements of lists described in their own section, `X_0`, `X_1`... like octez
+        let mut anonymous = 0;
+        while let Some((title, schema)) = sections.pop() {
+            if !seen.insert(title.clone()) {
+                continue;
+            }
+            let mut nested = vec![];
+            match schema {
+                Schema::Union {
+                    tag_size, cases, ..
+                } => {
+                    heading(&mut out, &title, '*');
+                    out.push_str(&format!("{}-bit tag\n\n", 8 * *tag_size as usize));
+                    for case in cases {
+                        heading(&mut out, &format!("{} (tag {})", case.name, case.tag), '=');
+                        let mut rows = vec![Row::new(
+                            "Tag",
+                            Some(*tag_size as usize),
+                            int_contents(false, 8 * tag_size),
+                        )];
+                        fields_rows(&case.fields, &mut rows, &mut nested, &mut anonymous);
+                        out.push_str(&table(&rows));
+                    }
+                }
+                Schema::Obj { fields, .. } => {
+                    heading(&mut out, &title, '*');
+                    let mut rows = vec![];
+                    fields_rows(fields, &mut rows, &mut nested, &mut anonymous);
+                    out.push_str(&table(&rows));
+                }
+                schema => {
+                    heading(&mut out, &title, '*');
+                    let mut rows = vec![];
+                    field_rows(
+                        "Unnamed field 0",
+                        schema,
+                        &mut rows,
+                        &mut nested,
+                        &mut anonymous,
+                    );
+                    out.push_str(&table(&rows));
+                }
+            }
+            // depth first, in the order they appear
+            nested.reverse();
+            sections.extend(nested);
+        }
+        out
+    }
+
+    /// What a table cell says about the value
+    fn contents(&self) -> String {
+        match self {
+            Schema::Bool => "boolean (0 for false, 255 for true)".to_string(),
+            Schema::Int { signed, bits } => int_contents(*signed, *bits),
+            Schema::Float => "double-precision floating-point number".to_string(),
+            Schema::Zarith { signed: false } => "N.t".to_string(),
+            Schema::Zarith { signed: true } => "Z.t".to_string(),
+            Schema::Bytes { .. } | Schema::String => "bytes".to_string(),
+            Schema::List { elements, .. } if **elements == BYTE => "bytes".to_string(),
+            Schema::Option { value } => format!("option of {}", value.contents()),
+            Schema::List {
+                length,
+                max_length,
+                elements,
+            } => list_contents(*length, *max_length, &elements.contents()),
+            Schema::Dynamic { value, .. } | Schema::Bounded { value, .. } => value.contents(),
+            Schema::Obj { name, .. } | Schema::Union { name, .. } | Schema::Ref { name } => {
+                format!("${}", name)
+            }
+        }
+    }
+
+    /// Whether the value is described by a single row, without size or presence before it
+    fn single_row(&self) -> bool {
+        match self {
+            Schema::Dynamic { .. } | Schema::Option { .. } => false,
+            Schema::Bounded { value, .. } => value.single_row(),
+            _ => true,
+        }
+    }
+
+    /// Objects and unions to describe in their own section
+    fn nested(&self) -> Option<(&'static str, &Schema)> {
+        match self {
+            Schema::Obj { name, .. } | Schema::Union { name, .. } => Some((name, self)),
+            Schema::Option { value }
+            | Schema::List {
+                elements: value, ..
+            }
+            | Schema::Dynamic { value, .. }
+            | Schema::Bounded { value, .. } => value.nested(),
+            _ => None,
+        }
+    }
+}
+
+/// Elements of the lists described as bytes
+const BYTE: Schema = Schema::Int {
+    signed: false,
+    bits: 8,
+};
+
+fn fields_size(fields: &[Field]) -> Option<usize> {
+    fields.iter().map(|field| field.schema.size()).sum()
+}
+
+fn list_contents(length: Option<usize>, max_length: Option<usize>, elements: &str) -> String {
+    match (length, max_length) {
+        (Some(length), _) => format!("sequence of exactly {} {}", length, elements),
+        (None, Some(max)) => format!("sequence of at most {} {}", max, elements),
+        (None, None) => format!("sequence of {}", elements),
+    }
+}
+
+fn int_contents(signed: bool, bits: u8) -> String {
+    let sign = if signed { "signed" } else { "unsigned" };
+    if bits == 8 {
+        format!("{} 8-bit integer", sign)
+    } else {
+        format!("{} {}-bit big-endian integer", sign, bits)
+    }
+}
+
+struct Row {
+    name: String,
+    size: String,
+    contents: String,
+}
+
+impl Row {
+    fn new(name: &str, size: Option<usize>, contents: String) -> Self {
+        let size = match size {
+            Some(1) => "1 byte".to_string(),
+            Some(size) => format!("{} bytes", size),
+            None => "Variable".to_string(),
+        };
+        Row {
+            name: name.to_string(),
+            size,
+            contents,
+        }
+    }
+}
+
+fn fields_rows<'a>(
+    fields: &'a [Field],
+    rows: &mut Vec<Row>,
+    nested: &mut Vec<(String, &'a Schema)>,
+    anonymous: &mut usize,
+) {
+    for (i, field) in fields.iter().enumerate() {
+        let name = match field.name {
+            Some(name) => name.to_string(),
+            None => format!("Unnamed field {}", i),
+        };
+        field_rows(&name, &field.schema, rows, nested, anonymous);
+    }
+}
+
+fn field_rows<'a>(
+    name: &str,
+    schema: &'a Schema,
+    rows: &mut Vec<Row>,
+    nested: &mut Vec<(String, &'a Schema)>,
+    anonymous: &mut usize,
+) {
+    match schema {
+        Schema::Dynamic { prefix, value } => {
+            let bits = match prefix {
+                SizePrefix::U8 => 8,
+                SizePrefix::U16 => 16,
+                SizePrefix::U30 => 30,
+                SizePrefix::U32 => 32,
+            };
+            rows.push(Row::new(
+                "# bytes in next field",
+                Some(prefix.bytes()),
+                int_contents(false, bits),
+            ));
+            field_rows(name, value, rows, nested, anonymous);
+        }
+        Schema::Bounded { value, .. } => field_rows(name, value, rows, nested, anonymous),
+        Schema::Option { value } => {
+            rows.push(Row::new(
+                &format!("? presence of field \"{}\"", name),
+                Some(1),
+                Schema::Bool.contents(),
+            ));
+            field_rows(name, value, rows, nested, anonymous);
+        }
+        // elements taking several rows, like the size of each one, get their own section
+        Schema::List {
+            length,
+            max_length,
+            elements,
+        } if !elements.single_row() => {
+            let section = format!("X_{}", anonymous);
+            *anonymous += 1;
+            let contents = list_contents(*length, *max_length, &format!("${}", section));
+            rows.push(Row::new(name, schema.size(), contents));
+            nested.push((section, elements));
+        }
+        schema => {
+            rows.push(Row::new(name, schema.size(), schema.contents()));
+            if let Some((name, schema)) = schema.nested() {
+                nested.push((name.to_string(), schema));
+            }
+        }
+    }
+}
+
+fn heading(out: &mut String, title: &str, underline: char) {
+    out.push_str(title);
+    out.push('\n');
+    out.extend(std::iter::repeat_n(underline, title.chars().count()));
+    out.push_str("\n\n");
+}
+
+fn table(rows: &[Row]) -> String {
+    let header = Row {
+        name: "Name".to_string(),
+        size: "Size".to_string(),
+        contents: "Contents".to_string(),
+    };
+    let width = |cell: fn(&Row) -> &String| {
+        rows.iter()
+            .chain([&header])
+            .map(|row| cell(row).chars().count())
+            .max()
+            .unwrap_or(0)
+    };
+    let widths = [
+        width(|r| &r.name),
+        width(|r| &r.size),
+        width(|r| &r.contents),
+    ];
+    let line = |fill: char| {
+        let mut line = String::from("+");
+        for w in widths {
+            line.extend(std::iter::repeat_n(fill, w + 2));
+            line.push('+');
+        }
+        line.push('\n');
+        line
+    };
+    let cells = |row: &Row| {
+        format!(
+            "| {:w0$} | {:w1$} | {:w2$} |\n",
+            row.name,
+            row.size,
+            row.contents,
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2]
+        )
+    };
+    let mut out = line('-');
+    out.push_str(&cells(&header));
+    out.push_str(&line('='));
+    for row in rows {
+        out.push_str(&cells(row));
+        out.push_str(&line('-'));
+    }
+    out.push('\n');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        encoding::direct::TezosEncoding,
+        p2p::{AckMessage, ConnectionMessage, Metadata},
+    };
+
+    use super::{Field, Schema};
+
+    #[test]
+    fn it_describes_the_handshake_messages() {
+        assert_eq!(Some(2), Metadata::schema().size());
+        assert_eq!(None, ConnectionMessage::schema().size());
+        let Schema::Obj { fields, .. } = ConnectionMessage::schema() else {
+            panic!("a struct is an obj");
+        };
+        assert_eq!(
+            Field {
+                name: Some("public_key"),
+                schema: Schema::Bytes { size: Some(32) },
+            },
+            fields[1]
+        );
+
+        let described = ConnectionMessage::schema().describe("connection_message");
+        assert!(described.starts_with("connection_message\n******************\n"));
+        assert!(described.contains("| nonce               | 24 bytes | bytes "));
+        assert!(described.contains("\nNetworkVersion\n"));
+        assert!(described.contains("| # bytes in next field  | 4 bytes  | unsigned 30-bit"));
+
+        // each point is prefixed by its size, in a section of its own
+        let described = AckMessage::schema().describe("ack");
+        assert!(described
+            .contains("| potential_peers_to_connect | Variable | sequence of at most 100 $X_0 "));
+        let point = &described[described.find("\nX_0\n***\n").expect("a section")..];
+        assert!(point.contains("| # bytes in next field | 4 bytes  | unsigned 30-bit"));
+        assert!(point.contains("| Unnamed field 0       | Variable | bytes "));
+
+        let json = serde_json::to_value(AckMessage::schema()).unwrap();
+        assert_eq!("union", json["kind"]);
+        assert_eq!(1, json["tag_size"]);
+        assert_eq!(255, json["cases"][1]["tag"]);
+        assert_eq!("motive", json["cases"][2]["fields"][0]["name"]);
+    }
+}
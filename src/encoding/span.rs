@@ -0,0 +1,140 @@
+/// Byte ranges of decoded values, to find where each field lies in a message.
+///
+/// Decoding with [`direct::decode_spans`](super::direct::decode_spans) records a `Span` for
+/// every field, list element and size prefix, [`Span::annotate`] prints them next to the bytes
+/// like a hex dump:
+/// ```text
+/// 00000000  01                                               Nack
+/// 00000001  00 01                                            Nack.motive
+/// 00000003  00 00 00 12                                      Nack.potential_peers_to_connect.size
+/// 00000007  00 00 00 0e                                      Nack.potential_peers_to_connect.0.size
+/// 0000000b  31 32 37 2e 30 2e 30 2e 31 3a 39 37 33 32        Nack.potential_peers_to_connect.0
+/// ```
+use std::ops::Range;
+
+/// Bytes `range` of the input, decoded as `name`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    /// Field name, index in a list, or variant name
+    pub name: String,
+    pub range: Range<usize>,
+    /// Nested values, in order
+    pub children: Vec<Span>,
+}
+
+/// Bytes per line of the dump
+const LINE: usize = 16;
+
+impl Span {
+    /// Span of `name` in `self` and its children, by dotted path like `Nack.motive`.
+    pub fn find(&self, path: &str) -> Option<&Span> {
+        path.split('.').try_fold(self, |span, name| {
+            span.children.iter().find(|child| child.name == name)
+        })
+    }
+
+    /// Hex dump of `bytes`, each line labelled by the path of the value its bytes belong to.
+    /// Bytes of a value not covered by its children, like the tag of a variant, are labelled
+    /// by the value itself, bytes past the end of the span are labelled `<not decoded>`.
+    pub fn annotate(&self, bytes: &[u8]) -> String {
+        let mut out = String::new();
+        self.lines("", bytes, &mut out);
+        if self.range.end < bytes.len() {
+            dump(
+                &mut out,
+                bytes,
+                self.range.end..bytes.len(),
+                "<not decoded>",
+            );
+        }
+        out
+    }
+
+    fn lines(&self, path: &str, bytes: &[u8], out: &mut String) {
+        let mut cursor = self.range.start;
+        for child in &self.children {
+            if cursor < child.range.start {
+                dump(out, bytes, cursor..child.range.start, path);
+            }
+            let path = match path {
+                "" => child.name.clone(),
+                path => format!("{}.{}", path, child.name),
+            };
+            child.lines(&path, bytes, out);
+            cursor = child.range.end;
+        }
+        if cursor < self.range.end {
+            dump(out, bytes, cursor..self.range.end, path);
+        }
+    }
+}
+
+fn dump(out: &mut String, bytes: &[u8], range: Range<usize>, label: &str) {
+    let end = range.end.min(bytes.len());
+    for (i, line) in bytes[range.start.min(end)..end].chunks(LINE).enumerate() {
+        let hex = line
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let label = if i == 0 { label } else { "" };
+        out.push_str(
+            format!(
+                "{:08x}  {:w$}  {}",
+                range.start + i * LINE,
+                hex,
+                label,
+                w = 3 * LINE - 1
+            )
+            .trim_end(),
+        );
+        out.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::{
+        encoding::direct::{annotated, decode_spans, to_bytes_no_header},
+        p2p::{AckMessage, Metadata},
+    };
+
+    #[test]
+    fn it_annotates_decoded_bytes() -> Result<()> {
+        let nack = AckMessage::Nack {
+            motive: 1.into(),
+            potential_peers_to_connect: vec!["127.0.0.1:9732".parse()?],
+        };
+        let bytes = to_bytes_no_header(&nack)?;
+        let (decoded, span) = decode_spans::<AckMessage>(&bytes);
+        assert_eq!(nack, decoded?);
+        assert_eq!(0..25, span.range);
+        assert_eq!(
+            Some(7..25),
+            span.find("Nack.potential_peers_to_connect.0")
+                .map(|span| span.range.clone())
+        );
+        assert_eq!(
+            "00000000  01                                               Nack\n\
+             00000001  00 01                                            Nack.motive\n\
+             00000003  00 00 00 12                                      \
+             Nack.potential_peers_to_connect.size\n\
+             00000007  00 00 00 0e                                      \
+             Nack.potential_peers_to_connect.0.size\n\
+             0000000b  31 32 37 2e 30 2e 30 2e 31 3a 39 37 33 32        \
+             Nack.potential_peers_to_connect.0\n",
+            span.annotate(&bytes)
+        );
+
+        let dump = annotated::<Metadata>(&[0, 1, 2]);
+        assert!(dump.starts_with("00000000  00 "));
+        assert!(dump.contains("  disable_mempool\n00000001  01"));
+        assert!(dump.contains(
+            "00000002  02                                               <not decoded>\n"
+        ));
+        assert!(dump.ends_with("error: Invalid boolean 0x01, expected 0x00 or 0xff\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+/// Arbitrary precision integers, octez `n` and `z` encodings.
+///
+/// In binary they are little-endian groups of 7 bits, the high bit of each byte tells whether
+/// another byte follows. The first byte of a `Zarith` holds the sign in its second bit and
+/// only 6 bits of the absolute value. In json they are decimal strings.
+use std::{fmt, str::FromStr};
+
+use num_bigint::{BigInt, BigUint, Sign};
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use super::error::Error;
+
+const CONTINUATION: u8 = 0x80;
+const Z_SIGN: u8 = 0x40;
+
+/// Natural number, octez `n`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Natural(pub BigUint);
+
+/// Integer, octez `z`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Zarith(pub BigInt);
+
+impl Natural {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.0.to_radix_le(128);
+        mark_continuations(&mut bytes);
+        bytes
+    }
+
+    /// Decodes a natural at the start of `bytes`, returns it with the number of bytes used.
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        let groups = groups(bytes)?;
+        let digits: Vec<u8> = groups.iter().map(|b| b & !CONTINUATION).collect();
+        let value = BigUint::from_radix_le(&digits, 128).ok_or(Error::InvalidZarith)?;
+        Ok((Natural(value), groups.len()))
+    }
+}
+
+impl Zarith {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let abs = self.0.magnitude();
+        let low = (abs % 64u8).to_u32_digits().first().copied().unwrap_or(0) as u8;
+        let high: BigUint = abs >> 6;
+        let mut bytes = vec![low];
+        if self.0.sign() == Sign::Minus {
+            bytes[0] |= Z_SIGN;
+        }
+        if high != BigUint::default() {
+            bytes.extend(high.to_radix_le(128));
+        }
+        mark_continuations(&mut bytes);
+        bytes
+    }
+
+    /// Decodes an integer at the start of `bytes`, returns it with the number of bytes used.
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        let groups = groups(bytes)?;
+        let digits: Vec<u8> = groups[1..].iter().map(|b| b & !CONTINUATION).collect();
+        let high = BigUint::from_radix_le(&digits, 128).ok_or(Error::InvalidZarith)?;
+        let abs = (high << 6) + BigUint::from(groups[0] & 0x3f);
+        let sign = if groups[0] & Z_SIGN != 0 {
+            Sign::Minus
+        } else {
+            Sign::Plus
+        };
+        Ok((Zarith(BigInt::from_biguint(sign, abs)), groups.len()))
+    }
+}
+
+/// Sets the high bit of every byte but the last
+fn mark_continuations(bytes: &mut [u8]) {
+    if let Some((_, init)) = bytes.split_last_mut() {
+        init.iter_mut().for_each(|b| *b |= CONTINUATION);
+    }
+}
+
+/// Bytes of the number at the start of `bytes`, rejects trailing zeros like octez.
+fn groups(bytes: &[u8]) -> Result<&[u8], Error> {
+    let len = bytes
+        .iter()
+        .position(|b| b & CONTINUATION == 0)
+        .ok_or(Error::UnsufficentBytes)?
+        + 1;
+    if len > 1 && bytes[len - 1] == 0 {
+        return Err(Error::InvalidZarith);
+    }
+    Ok(&bytes[..len])
+}
+
+macro_rules! conversions {
+    ($name:ident, $big:ty, $($int:ty),*) => {
+        $(
+            impl From<$int> for $name {
+                fn from(value: $int) -> Self {
+                    Self(<$big>::from(value))
+                }
+            }
+
+            impl TryFrom<&$name> for $int {
+                type Error = Error;
+
+                fn try_from(value: &$name) -> Result<Self, Self::Error> {
+                    <$int>::try_from(&value.0).map_err(|_| Error::NumberOverflow {
+                        value: value.0.to_string(),
+                        target: stringify!($int),
+                    })
+                }
+            }
+        )*
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = num_bigint::ParseBigIntError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                s.parse().map(Self)
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                if serializer.is_human_readable() {
+                    serializer.serialize_str(&self.to_string())
+                } else {
+                    serializer.serialize_bytes(&self.to_bytes())
+                }
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                if deserializer.is_human_readable() {
+                    let s = <String as Deserialize>::deserialize(deserializer)?;
+                    s.parse().map_err(de::Error::custom)
+                } else {
+                    let bytes = deserializer.deserialize_seq(ZarithVisitor)?;
+                    let (value, _) = Self::from_bytes(&bytes).map_err(de::Error::custom)?;
+                    Ok(value)
+                }
+            }
+        }
+    };
+}
+
+conversions!(Natural, BigUint, u8, u16, u32, u64, u128);
+conversions!(Zarith, BigInt, i8, i16, i32, i64, i128, u64);
+
+/// Collects the bytes of a number, up to the first one without the continuation bit.
+struct ZarithVisitor;
+
+impl<'de> Visitor<'de> for ZarithVisitor {
+    type Value = Vec<u8>;
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a zarith number")
+    }
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut bytes = vec![];
+        loop {
+            match seq.next_element::<u8>()? {
+                Some(b) => {
+                    bytes.push(b);
+                    if b & CONTINUATION == 0 {
+                        return Ok(bytes);
+                    }
+                }
+                None => return Err(de::Error::invalid_length(bytes.len(), &self)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::{BigInt, BigUint};
+    use proptest::{collection::vec, proptest};
+
+    use crate::encoding::{
+        bin::{from_bytes, to_bytes_no_header},
+        error::Error,
+    };
+
+    use super::{Natural, Zarith};
+
+    #[test]
+    fn it_encodes_like_octez() {
+        assert_eq!(vec![0], Natural::from(0u8).to_bytes());
+        assert_eq!(vec![0x7f], Natural::from(127u8).to_bytes());
+        assert_eq!(vec![0x80, 0x01], Natural::from(128u8).to_bytes());
+        assert_eq!(vec![0xac, 0x02], Natural::from(300u16).to_bytes());
+        assert_eq!(vec![0x00], Zarith::from(0i8).to_bytes());
+        assert_eq!(vec![0x3f], Zarith::from(63i8).to_bytes());
+        assert_eq!(vec![0x80, 0x01], Zarith::from(64i8).to_bytes());
+        assert_eq!(vec![0x41], Zarith::from(-1i8).to_bytes());
+        assert_eq!(vec![0xc0, 0x01], Zarith::from(-64i8).to_bytes());
+        // 1 tez in mutez
+        assert_eq!(
+            vec![0x80, 0x89, 0x7a],
+            Zarith::from(1_000_000i32).to_bytes()
+        );
+    }
+
+    #[test]
+    fn it_rejects_invalid_numbers() {
+        assert!(matches!(
+            Natural::from_bytes(&[0x80, 0x00]),
+            Err(Error::InvalidZarith)
+        ));
+        assert!(matches!(
+            Zarith::from_bytes(&[0x81]),
+            Err(Error::UnsufficentBytes)
+        ));
+        assert!(matches!(
+            u8::try_from(&Natural::from(256u16)),
+            Err(Error::NumberOverflow { target: "u8", .. })
+        ));
+        assert!(matches!(
+            u64::try_from(&Zarith::from(-1i8)),
+            Err(Error::NumberOverflow { .. })
+        ));
+    }
+
+    #[test]
+    fn it_uses_decimal_strings_in_json() {
+        let big: Zarith = "-123456789012345678901234567890".parse().unwrap();
+        let json = serde_json::to_string(&big).unwrap();
+        assert_eq!(r#""-123456789012345678901234567890""#, json);
+        assert_eq!(big, serde_json::from_str(&json).unwrap());
+    }
+
+    proptest! {
+        #[test]
+        fn it_round_trips_naturals(digits in vec(0u32.., 0..8), trailing: u8) {
+            let natural = Natural(BigUint::new(digits));
+            let mut bytes = to_bytes_no_header(&natural).unwrap();
+            let (decoded, used) = Natural::from_bytes(&bytes).unwrap();
+            assert_eq!(natural, decoded);
+            assert_eq!(bytes.len(), used);
+            assert_eq!(natural, from_bytes(&bytes).unwrap());
+            // the encoding ends by itself
+            bytes.push(trailing);
+            assert_eq!((natural, used), Natural::from_bytes(&bytes).unwrap());
+        }
+
+        #[test]
+        fn it_round_trips_integers(digits in vec(0u32.., 0..8), negative: bool) {
+            let sign = if negative { num_bigint::Sign::Minus } else { num_bigint::Sign::Plus };
+            let zarith = Zarith(BigInt::new(sign, digits));
+            let bytes = to_bytes_no_header(&zarith).unwrap();
+            assert_eq!(zarith, from_bytes(&bytes).unwrap());
+        }
+
+        #[test]
+        fn it_round_trips_i64(value: i64) {
+            let zarith = Zarith::from(value);
+            let (decoded, _) = Zarith::from_bytes(&zarith.to_bytes()).unwrap();
+            assert_eq!(value, i64::try_from(&decoded).unwrap());
+        }
+    }
+}
@@ -0,0 +1,216 @@
+/// Tezos hashes and key hashes, Base58Check encoded in json and raw in the binary encoding.
+use std::{fmt, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::encoding::{
+    b58::{self, b58_hash},
+    bin::deserialize_fixed,
+    direct::TezosEncoding,
+    error::B58Error,
+};
+
+b58_hash!(
+    /// `B...`
+    BlockHash,
+    b58::BLOCK_HASH,
+    32
+);
+b58_hash!(
+    /// `o...`
+    OperationHash,
+    b58::OPERATION_HASH,
+    32
+);
+b58_hash!(
+    /// `Lo...`
+    OperationListHash,
+    b58::OPERATION_LIST_HASH,
+    32
+);
+b58_hash!(
+    /// `LLo...`
+    OperationListListHash,
+    b58::OPERATION_LIST_LIST_HASH,
+    32
+);
+b58_hash!(
+    /// `P...`
+    ProtocolHash,
+    b58::PROTOCOL_HASH,
+    32
+);
+b58_hash!(
+    /// `Co...`
+    ContextHash,
+    b58::CONTEXT_HASH,
+    32
+);
+b58_hash!(
+    /// `Net...`
+    ChainId,
+    b58::CHAIN_ID,
+    4
+);
+b58_hash!(
+    /// Peer id `id...`, the Blake2b-128 hash of the peer public key.
+    CryptoboxPublicKeyHash,
+    b58::CRYPTOBOX_PUBLIC_KEY_HASH,
+    16
+);
+b58_hash!(
+    /// `tz1...`
+    Ed25519PublicKeyHash,
+    b58::ED25519_PUBLIC_KEY_HASH,
+    20
+);
+b58_hash!(
+    /// `tz2...`
+    Secp256k1PublicKeyHash,
+    b58::SECP256K1_PUBLIC_KEY_HASH,
+    20
+);
+b58_hash!(
+    /// `tz3...`
+    P256PublicKeyHash,
+    b58::P256_PUBLIC_KEY_HASH,
+    20
+);
+
+/// Implicit account address, tagged by its curve in the binary encoding
+/// (`Signature.Public_key_hash.encoding`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, TezosEncoding)]
+pub enum PublicKeyHash {
+    Ed25519(Ed25519PublicKeyHash),
+    Secp256k1(Secp256k1PublicKeyHash),
+    P256(P256PublicKeyHash),
+}
+
+impl PublicKeyHash {
+    fn tag(&self) -> u8 {
+        match self {
+            PublicKeyHash::Ed25519(_) => 0,
+            PublicKeyHash::Secp256k1(_) => 1,
+            PublicKeyHash::P256(_) => 2,
+        }
+    }
+    fn hash(&self) -> &[u8] {
+        match self {
+            PublicKeyHash::Ed25519(h) => h.as_ref(),
+            PublicKeyHash::Secp256k1(h) => h.as_ref(),
+            PublicKeyHash::P256(h) => h.as_ref(),
+        }
+    }
+}
+
+impl fmt::Display for PublicKeyHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PublicKeyHash::Ed25519(h) => h.fmt(f),
+            PublicKeyHash::Secp256k1(h) => h.fmt(f),
+            PublicKeyHash::P256(h) => h.fmt(f),
+        }
+    }
+}
+
+impl FromStr for PublicKeyHash {
+    type Err = B58Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with(b58::ED25519_PUBLIC_KEY_HASH.readable) {
+            s.parse().map(PublicKeyHash::Ed25519)
+        } else if s.starts_with(b58::SECP256K1_PUBLIC_KEY_HASH.readable) {
+            s.parse().map(PublicKeyHash::Secp256k1)
+        } else if s.starts_with(b58::P256_PUBLIC_KEY_HASH.readable) {
+            s.parse().map(PublicKeyHash::P256)
+        } else {
+            Err(B58Error::UnexpectedPrefix {
+                expected: "tz1, tz2 or tz3",
+                value: s.to_string(),
+            })
+        }
+    }
+}
+
+impl Serialize for PublicKeyHash {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_bytes(&[&[self.tag()], self.hash()].concat())
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PublicKeyHash {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            s.parse().map_err(de::Error::custom)
+        } else {
+            let bytes = deserialize_fixed::<_, 21>(deserializer)?;
+            let mut hash = [0; 20];
+            hash.copy_from_slice(&bytes[1..]);
+            match bytes[0] {
+                0 => Ok(PublicKeyHash::Ed25519(hash.into())),
+                1 => Ok(PublicKeyHash::Secp256k1(hash.into())),
+                2 => Ok(PublicKeyHash::P256(hash.into())),
+                tag => Err(de::Error::custom(format!(
+                    "unknown public key hash tag {}",
+                    tag
+                ))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::encoding::bin::{from_bytes, to_bytes_no_header};
+
+    use super::{BlockHash, ChainId, PublicKeyHash};
+
+    #[test]
+    fn it_serializes_hashes_in_json_and_binary() {
+        let genesis: BlockHash = "BLockGenesisGenesisGenesisGenesisGenesisf79b5d1CoW2"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            json!("BLockGenesisGenesisGenesisGenesisGenesisf79b5d1CoW2"),
+            serde_json::to_value(genesis).unwrap()
+        );
+        let bytes = to_bytes_no_header(&genesis).unwrap();
+        assert_eq!(genesis.as_ref(), &bytes[..]);
+        assert_eq!(genesis, from_bytes::<BlockHash>(&bytes).unwrap());
+
+        let chain_id: ChainId = serde_json::from_value(json!("NetXdQprcVkpaWU")).unwrap();
+        assert_eq!(&[0x7a, 0x06, 0xa7, 0x70], chain_id.as_ref());
+    }
+
+    #[test]
+    fn it_tags_public_key_hashes() {
+        for (address, tag) in [
+            ("tz1KqTpEZ7Yob7QbPE4Hy4Wo8fHG8LhKxZSx", 0),
+            ("tz2BFTyPeYRzxd5aiBchbXN3WCZhx7BqbMBq", 1),
+            ("tz3bvNMQ95vfAYtG8193ymshqjSvmxiCUuR5", 2),
+        ] {
+            let pkh: PublicKeyHash = address.parse().unwrap();
+            assert_eq!(address, pkh.to_string());
+            let bytes = to_bytes_no_header(&pkh).unwrap();
+            assert_eq!(21, bytes.len());
+            assert_eq!(tag, bytes[0]);
+            assert_eq!(pkh, from_bytes::<PublicKeyHash>(&bytes).unwrap());
+        }
+        assert!("KT1BEqzn5Wx8uJrZNvuS9DVHmLvG9td3fDLi"
+            .parse::<PublicKeyHash>()
+            .is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::Result;
+use blake2::digest::{consts::U16, Digest};
+use blake2::Blake2b;
+use crypto_box::{aead::rand_core::CryptoRngCore, Nonce as CryptoNonce, PublicKey, SecretKey};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+pub use crate::hash::CryptoboxPublicKeyHash;
+
+pub mod pow;
+
+#[derive(Debug, Error)]
+pub enum IdentityError {
+    #[error("Peer id {stored} doesn't match the public key, expected {expected}")]
+    PeerIdMismatch {
+        stored: CryptoboxPublicKeyHash,
+        expected: CryptoboxPublicKeyHash,
+    },
+}
+
+/// Peer ids are the Blake2b-128 hash of the public key
+impl From<&PublicKey> for CryptoboxPublicKeyHash {
+    fn from(pk: &PublicKey) -> Self {
+        Self::from(<[u8; 16]>::from(Blake2b::<U16>::digest(pk.as_bytes())))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct JsonNonce(CryptoNonce);
+
+impl From<[u8; 24]> for JsonNonce {
+    fn from(value: [u8; 24]) -> Self {
+        Self(CryptoNonce::from(value))
+    }
+}
+
+impl JsonNonce {
+    pub fn bytes(&self) -> [u8; 24] {
+        let mut bytes = [0; 24];
+        bytes.copy_from_slice(&self.0[0..24]);
+        bytes
+    }
+}
+
+impl AsRef<[u8]> for JsonNonce {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+/// Peer Identity used for handshake
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Identity {
+    pub peer_id: CryptoboxPublicKeyHash,
+    #[serde(serialize_with = "crate::encoding::json::serialize_public_key")]
+    pub public_key: PublicKey,
+    #[serde(serialize_with = "crate::encoding::json::serialize_secret_key")]
+    pub secret_key: SecretKey,
+    pub proof_of_work_stamp: JsonNonce,
+}
+
+impl Identity {
+    /// Reads an octez `identity.json`, and checks that its peer id matches its public key.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let f = File::open(path)?;
+        let reader = BufReader::new(f);
+        let res: Self = serde_json::from_reader(reader)?;
+        res.validate()?;
+        Ok(res)
+    }
+
+    pub fn validate(&self) -> Result<(), IdentityError> {
+        let expected = CryptoboxPublicKeyHash::from(&self.public_key);
+        if self.peer_id == expected {
+            Ok(())
+        } else {
+            Err(IdentityError::PeerIdMismatch {
+                stored: self.peer_id,
+                expected,
+            })
+        }
+    }
+
+    /// Generates a fresh key pair and searches a proof of work stamp for it on all cores.
+    /// Expect this to take a while with the default difficulty, see `pow::Search` for
+    /// progress reporting and checkpoints.
+    pub fn generate<R>(difficulty: u8, rng: &mut R) -> Result<Self>
+    where
+        R: CryptoRngCore,
+    {
+        let secret_key = SecretKey::generate(rng);
+        Ok(pow::Search::new(secret_key, difficulty, rng).run()?)
+    }
+
+    /// Writes the identity in the same json format as octez `identity.json`.
+    /// Fails if the file already exists.
+    pub fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let f = File::create_new(path)?;
+        let mut writer = BufWriter::new(f);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use crypto_box::PublicKey;
+    use rand::thread_rng;
+
+    use super::{pow::check_proof_of_work, CryptoboxPublicKeyHash, Identity, IdentityError};
+
+    #[test]
+    fn it_derives_peer_id_from_public_key() {
+        let pk = PublicKey::from([
+            0x3b, 0x2c, 0x39, 0x50, 0xd9, 0xc5, 0x9a, 0x5c, 0x19, 0xaf, 0x7b, 0xe3, 0x9c, 0xe5,
+            0x84, 0x45, 0x23, 0xbc, 0x00, 0x26, 0x51, 0xcd, 0x45, 0x41, 0x7e, 0x63, 0x54, 0x62,
+            0xce, 0x66, 0x6f, 0x07,
+        ]);
+        let peer_id = CryptoboxPublicKeyHash::from(&pk);
+        assert_eq!("idrpbo9Ru5pYiWTg1i2VPABG6Catfm", peer_id.to_string());
+        assert_eq!(
+            Ok(peer_id),
+            "idrpbo9Ru5pYiWTg1i2VPABG6Catfm".parse().map_err(|_| ())
+        );
+        assert!("idrpbo9Ru5pYiWTg1i2VPABG6Catfn"
+            .parse::<CryptoboxPublicKeyHash>()
+            .is_err());
+    }
+
+    #[test]
+    fn it_rejects_identity_with_wrong_peer_id() {
+        let path = std::env::temp_dir().join(format!("identity-{}.json", std::process::id()));
+        let other = CryptoboxPublicKeyHash::from(&PublicKey::from([0; 32]));
+        let mut f = std::fs::File::create(&path).unwrap();
+        write!(
+            f,
+            r#"{{
+                "peer_id": "{}",
+                "public_key": "3b2c3950d9c59a5c19af7be39ce5844523bc002651cd45417e635462ce666f07",
+                "secret_key": "d0c24b1537d8651ebc39951030c1fccea83188d563ac8cf9667f7ccb7765b1ba",
+                "proof_of_work_stamp": "ea2fa50b542755be6bc4a53188d758cf4e7d4e085082f4bd"
+            }}"#,
+            other
+        )
+        .unwrap();
+        let err = Identity::from_file(&path).unwrap_err();
+        std::fs::remove_file(path).unwrap();
+        assert!(matches!(
+            err.downcast_ref::<IdentityError>(),
+            Some(IdentityError::PeerIdMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn it_generates_an_identity() {
+        let identity = Identity::generate(4, &mut thread_rng()).expect("should generate");
+        assert!(check_proof_of_work(
+            &identity.public_key,
+            identity.proof_of_work_stamp.as_ref(),
+            4
+        ));
+        assert!(identity.validate().is_ok());
+    }
+}
@@ -0,0 +1,446 @@
+/// Proof of work attached to peer identities.
+///
+/// Same scheme as octez `Crypto_box.check_proof_of_work`: the Blake2b-256 hash of
+/// `public_key || proof_of_work_stamp` must be below a target derived from the difficulty.
+/// Only integer difficulties are supported, the target is then simply a number of leading
+/// zero bits.
+/// Note that octez reads the hash as a little endian number (`Z.of_bits`), so the leading
+/// zero bits are at the end of the hash bytes.
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use blake2::digest::{consts::U32, Digest};
+use blake2::Blake2b;
+use crypto_box::{aead::rand_core::CryptoRngCore, PublicKey, SecretKey};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::p2p::Nonce;
+
+use super::{CryptoboxPublicKeyHash, Identity, JsonNonce};
+
+/// Default difficulty used by `octez-node identity generate`
+pub const DEFAULT_DIFFICULTY: u8 = 26;
+
+pub const STAMP_LENGTH: usize = 24;
+
+/// Number of stamps a worker checks between two updates of its counter
+const BATCH: u64 = 4096;
+
+type Blake2b256 = Blake2b<U32>;
+
+/// Checks that `stamp` is a valid proof of work for `pk` at the given `difficulty`.
+pub fn check_proof_of_work(pk: &PublicKey, stamp: &[u8], difficulty: u8) -> bool {
+    let hash = Blake2b256::new()
+        .chain_update(pk.as_bytes())
+        .chain_update(stamp)
+        .finalize();
+    leading_zero_bits(&hash) >= difficulty as u32
+}
+
+/// Leading zero bits of `hash` read as a little endian number
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut zeros = 0;
+    for b in hash.iter().rev() {
+        zeros += b.leading_zeros();
+        if *b != 0 {
+            break;
+        }
+    }
+    zeros
+}
+
+#[derive(Debug, Error)]
+pub enum PowError {
+    #[error("Proof of work search cancelled")]
+    Cancelled,
+    #[error("Checkpoint IO error `{0}`")]
+    IO(#[from] std::io::Error),
+    #[error("Invalid checkpoint `{0}`")]
+    Checkpoint(#[from] serde_json::Error),
+    #[error("Checkpoint with {0} workers, expected 1 to 65535")]
+    Workers(usize),
+}
+
+/// Progress of a search, reported periodically to the `on_progress` callback.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    /// Stamps checked so far, including the ones of previous runs
+    pub attempts: u64,
+    /// Attempts per second during this run
+    pub rate: f64,
+    /// Remaining time assuming the average `2^difficulty` attempts are needed
+    pub eta: Duration,
+}
+
+/// State of a search, enough to resume it deterministically.
+///
+/// The stamp space is partitioned between `n` workers: worker `i` checks the stamps
+/// `start + i + k * n` for `k = 0, 1, ...` and has already checked `attempts[i]` of them.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    #[serde(serialize_with = "crate::encoding::json::serialize_secret_key")]
+    pub secret_key: SecretKey,
+    pub difficulty: u8,
+    pub start: JsonNonce,
+    pub attempts: Vec<u64>,
+}
+
+impl Checkpoint {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, PowError> {
+        let reader = BufReader::new(File::open(path)?);
+        let checkpoint: Self = serde_json::from_reader(reader)?;
+        checkpoint.workers()?;
+        Ok(checkpoint)
+    }
+
+    /// Writes to a temporary file first so that a crash never leaves a truncated checkpoint.
+    pub fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), PowError> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Number of workers, each one steps over the others with a u16
+    fn workers(&self) -> Result<u16, PowError> {
+        match u16::try_from(self.attempts.len()) {
+            Ok(workers) if workers > 0 => Ok(workers),
+            _ => Err(PowError::Workers(self.attempts.len())),
+        }
+    }
+
+    fn total_attempts(&self) -> u64 {
+        self.attempts.iter().sum()
+    }
+}
+
+/// Multi-threaded proof of work search for a new identity.
+///
+/// Kind of Builder pattern, same as `Handshake`.
+pub struct Search<'a> {
+    checkpoint: Checkpoint,
+    checkpoint_path: Option<PathBuf>,
+    cancel: Option<Arc<AtomicBool>>,
+    on_progress: Option<Box<dyn FnMut(Progress) + Send + 'a>>,
+    interval: Duration,
+}
+
+impl<'a> Search<'a> {
+    /// New search from a random point of the stamp space, using all the available cores.
+    pub fn new<R>(secret_key: SecretKey, difficulty: u8, rng: &mut R) -> Self
+    where
+        R: CryptoRngCore,
+    {
+        let start = JsonNonce::from(Nonce::generate(rng).bytes());
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        Self::from_checkpoint(Checkpoint {
+            secret_key,
+            difficulty,
+            start,
+            attempts: vec![0; threads],
+        })
+    }
+
+    /// Resumes the search saved in `path`, further checkpoints are written in the same file.
+    pub fn resume<P: AsRef<Path>>(path: P) -> Result<Self, PowError> {
+        let checkpoint = Checkpoint::from_file(&path)?;
+        Ok(Self::from_checkpoint(checkpoint).checkpoint(path.as_ref()))
+    }
+
+    pub fn from_checkpoint(checkpoint: Checkpoint) -> Self {
+        Search {
+            checkpoint,
+            checkpoint_path: None,
+            cancel: None,
+            on_progress: None,
+            interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Sets the number of workers. Only possible before the search has started as
+    /// it would change the partitioning of a resumed search.
+    pub fn threads(mut self, threads: usize) -> Self {
+        if self.checkpoint.total_attempts() == 0 {
+            self.checkpoint.attempts = vec![0; threads.clamp(1, u16::MAX as usize)];
+        }
+        self
+    }
+
+    /// Periodically saves the state of the search in `path`, the file is removed once a stamp
+    /// is found.
+    pub fn checkpoint<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.checkpoint_path = Some(path.into());
+        self
+    }
+
+    /// The search stops with `PowError::Cancelled` as soon as `cancel` is set.
+    pub fn cancel_on(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    pub fn on_progress<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(Progress) + Send + 'a,
+    {
+        self.on_progress = Some(Box::new(f));
+        self
+    }
+
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn run(mut self) -> Result<Identity, PowError> {
+        let public_key = self.checkpoint.secret_key.public_key();
+        let workers = self.checkpoint.workers()?;
+        let counters: Vec<_> = self
+            .checkpoint
+            .attempts
+            .iter()
+            .map(|a| AtomicU64::new(*a))
+            .collect();
+        let stop = AtomicBool::new(false);
+        let never = AtomicBool::new(false);
+        let cancel_flag = self.cancel.clone();
+        let cancel = cancel_flag.as_deref().unwrap_or(&never);
+        let resumed = self.checkpoint.total_attempts();
+        let started = Instant::now();
+
+        let found = thread::scope(|scope| {
+            let (tx, rx) = mpsc::channel();
+            for (i, counter) in counters.iter().enumerate() {
+                let worker = Worker {
+                    public_key: &public_key,
+                    difficulty: self.checkpoint.difficulty,
+                    start: self.checkpoint.start.bytes(),
+                    index: i,
+                    step: workers,
+                    counter,
+                    stop: &stop,
+                    cancel,
+                };
+                let tx = tx.clone();
+                scope.spawn(move || tx.send(worker.run()));
+            }
+            drop(tx);
+
+            let mut found = None;
+            let mut running = workers as usize;
+            while running > 0 {
+                match rx.recv_timeout(self.interval) {
+                    Ok(res) => {
+                        running -= 1;
+                        if res.is_some() && found.is_none() {
+                            found = res;
+                            stop.store(true, Ordering::Relaxed);
+                        }
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        self.save(&counters)?;
+                        self.report(&counters, resumed, started);
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            Ok::<_, PowError>(found)
+        })?;
+
+        self.save(&counters)?;
+        self.report(&counters, resumed, started);
+        let stamp = found.ok_or(PowError::Cancelled)?;
+        if let Some(path) = &self.checkpoint_path {
+            fs::remove_file(path)?;
+        }
+        Ok(Identity {
+            peer_id: CryptoboxPublicKeyHash::from(&public_key),
+            public_key,
+            secret_key: self.checkpoint.secret_key,
+            proof_of_work_stamp: JsonNonce::from(stamp),
+        })
+    }
+
+    fn save(&mut self, counters: &[AtomicU64]) -> Result<(), PowError> {
+        for (a, c) in self.checkpoint.attempts.iter_mut().zip(counters) {
+            *a = c.load(Ordering::Relaxed);
+        }
+        match &self.checkpoint_path {
+            Some(path) => self.checkpoint.to_file(path),
+            None => Ok(()),
+        }
+    }
+
+    fn report(&mut self, counters: &[AtomicU64], resumed: u64, started: Instant) {
+        let Some(on_progress) = self.on_progress.as_mut() else {
+            return;
+        };
+        let attempts: u64 = counters.iter().map(|c| c.load(Ordering::Relaxed)).sum();
+        let rate = (attempts - resumed) as f64 / started.elapsed().as_secs_f64();
+        let expected = 2f64.powi(self.checkpoint.difficulty as i32);
+        let remaining = (expected - attempts as f64).max(0.);
+        let eta = if rate > 0. {
+            Duration::from_secs_f64(remaining / rate)
+        } else {
+            Duration::MAX
+        };
+        on_progress(Progress {
+            attempts,
+            rate,
+            eta,
+        });
+    }
+}
+
+struct Worker<'s> {
+    public_key: &'s PublicKey,
+    difficulty: u8,
+    start: [u8; STAMP_LENGTH],
+    index: usize,
+    step: u16,
+    counter: &'s AtomicU64,
+    stop: &'s AtomicBool,
+    cancel: &'s AtomicBool,
+}
+
+impl Worker<'_> {
+    fn run(self) -> Option<[u8; STAMP_LENGTH]> {
+        let mut attempts = self.counter.load(Ordering::Relaxed);
+        let mut stamp = Nonce::from(self.start);
+        // resumed searches don't start at the beginning of their partition
+        stamp.add(self.index as u64 + attempts * self.step as u64);
+        loop {
+            if self.stop.load(Ordering::Relaxed) || self.cancel.load(Ordering::Relaxed) {
+                return None;
+            }
+            for _ in 0..BATCH {
+                if check_proof_of_work(self.public_key, stamp.as_ref(), self.difficulty) {
+                    return Some(stamp.bytes());
+                }
+                stamp.inc_step(self.step);
+                attempts += 1;
+            }
+            self.counter.store(attempts, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::AtomicBool, Arc};
+
+    use crypto_box::{PublicKey, SecretKey};
+    use rand::thread_rng;
+
+    use super::{check_proof_of_work, leading_zero_bits, Checkpoint, PowError, Search};
+
+    #[test]
+    fn it_counts_leading_zero_bits() {
+        assert_eq!(0, leading_zero_bits(&[0, 0x80]));
+        assert_eq!(9, leading_zero_bits(&[0xff, 0x40, 0]));
+        assert_eq!(16, leading_zero_bits(&[0, 0]));
+    }
+
+    #[test]
+    fn it_checks_octez_generated_stamp() {
+        // identity generated by octez-node with the default difficulty
+        let pk = PublicKey::from([
+            0x3b, 0x2c, 0x39, 0x50, 0xd9, 0xc5, 0x9a, 0x5c, 0x19, 0xaf, 0x7b, 0xe3, 0x9c, 0xe5,
+            0x84, 0x45, 0x23, 0xbc, 0x00, 0x26, 0x51, 0xcd, 0x45, 0x41, 0x7e, 0x63, 0x54, 0x62,
+            0xce, 0x66, 0x6f, 0x07,
+        ]);
+        let stamp = [
+            0xea, 0x2f, 0xa5, 0x0b, 0x54, 0x27, 0x55, 0xbe, 0x6b, 0xc4, 0xa5, 0x31, 0x88, 0xd7,
+            0x58, 0xcf, 0x4e, 0x7d, 0x4e, 0x08, 0x50, 0x82, 0xf4, 0xbd,
+        ];
+        assert!(check_proof_of_work(&pk, &stamp, 26));
+        assert!(!check_proof_of_work(&pk, &[0; 24], 26));
+    }
+
+    #[test]
+    fn it_finds_a_valid_stamp_with_several_threads() {
+        let mut rng = thread_rng();
+        let identity = Search::new(SecretKey::generate(&mut rng), 10, &mut rng)
+            .threads(3)
+            .run()
+            .expect("should find a stamp");
+        assert!(check_proof_of_work(
+            &identity.public_key,
+            identity.proof_of_work_stamp.as_ref(),
+            10
+        ));
+    }
+
+    #[test]
+    fn it_resumes_deterministically() {
+        let mut rng = thread_rng();
+        let secret_key = SecretKey::generate(&mut rng);
+        let start = [0; 24].into();
+        let fresh = Search::from_checkpoint(Checkpoint {
+            secret_key: secret_key.clone(),
+            difficulty: 12,
+            start,
+            attempts: vec![0],
+        })
+        .run()
+        .expect("should find a stamp");
+        // the single worker checks the stamps in order, so resuming right before the
+        // found stamp must find the same one
+        let mut before = [0u8; 24];
+        before.copy_from_slice(fresh.proof_of_work_stamp.as_ref());
+        let found = u64::from_be_bytes(before[16..].try_into().unwrap());
+        let resumed = Search::from_checkpoint(Checkpoint {
+            secret_key,
+            difficulty: 12,
+            start: [0; 24].into(),
+            attempts: vec![found],
+        })
+        .run()
+        .expect("should find a stamp");
+        assert_eq!(
+            fresh.proof_of_work_stamp.as_ref(),
+            resumed.proof_of_work_stamp.as_ref()
+        );
+    }
+
+    #[test]
+    fn it_saves_a_checkpoint_when_cancelled() {
+        let mut rng = thread_rng();
+        let path = std::env::temp_dir().join(format!("pow-{}.json", std::process::id()));
+        let cancel = Arc::new(AtomicBool::new(true));
+        let res = Search::new(SecretKey::generate(&mut rng), 64, &mut rng)
+            .threads(2)
+            .checkpoint(&path)
+            .cancel_on(cancel)
+            .run();
+        assert!(matches!(res, Err(PowError::Cancelled)));
+        let checkpoint = Checkpoint::from_file(&path).expect("should be saved");
+        assert_eq!(2, checkpoint.attempts.len());
+        assert_eq!(64, checkpoint.difficulty);
+
+        // a checkpoint without workers would never find anything
+        let mut json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        json["attempts"] = serde_json::json!([]);
+        std::fs::write(&path, json.to_string()).unwrap();
+        assert!(matches!(
+            Checkpoint::from_file(&path),
+            Err(PowError::Workers(0))
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+}
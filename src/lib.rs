@@ -1,3 +1,8 @@
-mod encoding;
+// lets `#[derive(TezosEncoding)]` refer to `::tzhandhsake` inside this crate as well
+extern crate self as tzhandhsake;
+
+pub mod encoding;
+pub mod hash;
 pub mod identity;
+pub mod network;
 pub mod p2p;
@@ -1,39 +1,328 @@
-use std::{path::PathBuf, time::Duration};
+use std::{
+    io::Read,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
+use crypto_box::SecretKey;
 use rand::thread_rng;
-use tzhandhsake::{identity::Identity, p2p::handshake::Handshake};
+use tzhandhsake::{
+    identity::{
+        pow::{Search, DEFAULT_DIFFICULTY},
+        Identity,
+    },
+    network::Network,
+    p2p::{handshake::Handshake, registry, Metadata},
+};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 #[derive(Parser, Debug)]
-#[command(about = "Handshakes tezos nodes on Ghostnet")]
+#[command(
+    about = "Handshakes tezos nodes",
+    args_conflicts_with_subcommands = true
+)]
 struct Cli {
-    /// Ghostnet not to perform the handshake with
+    /// Node to perform the handshake with, defaults to a bootstrap peer of the network
     /// Format "ip:port"
-    #[arg(
-        short,
-        long,
-        required = false,
-        default_value = "ghostnet.tzinit.org:9732"
-    )]
-    node: String,
+    #[arg(short, long)]
+    node: Option<String>,
 
     #[arg(short, long, required = true)]
-    identity_path: PathBuf,
+    identity_path: Option<PathBuf>,
+
+    #[command(flatten)]
+    peer: PeerArgs,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Handshake options common to incoming and outgoing connections
+#[derive(Args, Debug)]
+struct PeerArgs {
+    /// mainnet, ghostnet, sandbox or the path of a .json/.toml network file
+    #[arg(long, default_value = "ghostnet")]
+    network: Network,
+
+    /// Minimum proof of work difficulty expected from the nodes, defaults to the network's
+    #[arg(long)]
+    expected_pow: Option<u8>,
+
+    /// Asks the nodes not to advertise us to their peers
+    #[arg(long)]
+    private_node: bool,
+
+    /// Asks the nodes not to send us operations
+    #[arg(long)]
+    disable_mempool: bool,
+}
+
+impl PeerArgs {
+    fn handshake(&self, identity: Identity) -> Result<Handshake> {
+        Ok(Handshake::identity(identity)
+            .with_network(&self.network)?
+            .with_pow_difficulty(self.expected_pow.unwrap_or(self.network.expected_pow))
+            .with_metadata(Metadata {
+                disable_mempool: self.disable_mempool,
+                private_node: self.private_node,
+            }))
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage peer identities
+    #[command(subcommand)]
+    Identity(IdentityCommand),
+    /// Waits for nodes to connect and handshakes them
+    Listen {
+        /// Address to listen on, defaults to the network port on all interfaces
+        /// Format "ip:port"
+        #[arg(short, long)]
+        addr: Option<String>,
+        #[arg(short, long)]
+        identity_path: PathBuf,
+        #[command(flatten)]
+        peer: PeerArgs,
+    },
+    /// Prints the binary encoding of a message, like `octez-codec describe`
+    Describe {
+        /// Message to describe, lists them when missing
+        name: Option<String>,
+        /// Prints the schema as json
+        #[arg(long)]
+        json: bool,
+    },
+    /// Converts messages between their binary encoding and json
+    #[command(subcommand)]
+    Codec(CodecCommand),
+}
+
+#[derive(Subcommand, Debug)]
+enum CodecCommand {
+    /// Prints a binary message as json
+    Decode {
+        /// Message name, see `describe`
+        name: String,
+        /// Hex, `@path` of a binary or hex file, or `-` for stdin
+        input: String,
+        /// Prints the bytes of every field before the json
+        #[arg(long)]
+        annotated: bool,
+    },
+    /// Prints the hex encoding of a json message
+    Encode {
+        /// Message name, see `describe`
+        name: String,
+        /// Json, `@path` of a json file, or `-` for stdin
+        input: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum IdentityCommand {
+    /// Generates a new identity.json, same as `octez-node identity generate`
+    Generate {
+        /// Number of leading zero bits required for the proof of work
+        #[arg(short, long, default_value_t = DEFAULT_DIFFICULTY)]
+        difficulty: u8,
+        /// Where to write the identity, the file must not exist
+        #[arg(short, long)]
+        out: PathBuf,
+        /// Number of threads searching the proof of work, defaults to all cores
+        #[arg(short, long)]
+        threads: Option<usize>,
+        /// Saves the search progress in this file, and resumes from it if it exists
+        #[arg(short, long)]
+        checkpoint: Option<PathBuf>,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Cli::parse();
-    println!("connecting to {}", args.node);
+    match args.command {
+        Some(Command::Identity(IdentityCommand::Generate {
+            difficulty,
+            out,
+            threads,
+            checkpoint,
+        })) => generate_identity(difficulty, out, threads, checkpoint).await,
+        Some(Command::Listen {
+            addr,
+            identity_path,
+            peer,
+        }) => listen(addr, identity_path, peer).await,
+        Some(Command::Describe { name, json }) => describe(name, json),
+        Some(Command::Codec(command)) => codec(command),
+        None => {
+            let identity_path = args
+                .identity_path
+                .expect("required by clap when there is no subcommand");
+            handshake(args.node, identity_path, args.peer).await
+        }
+    }
+}
+
+async fn handshake(node: Option<String>, identity_path: PathBuf, peer: PeerArgs) -> Result<()> {
+    let node = match node {
+        Some(node) => node,
+        None => match peer.network.bootstrap_addrs().into_iter().next() {
+            Some(node) => node,
+            None => bail!("the network has no bootstrap peer, use --node"),
+        },
+    };
+    println!("connecting to {}", node);
     let mut rng = thread_rng();
-    let _chan = Handshake::identity(Identity::from_file(args.identity_path)?)
+    let chan = peer
+        .handshake(Identity::from_file(identity_path)?)?
         .generate_nonce(&mut rng)
-        .connect(args.node)
+        .connect(node)
         .await?;
 
-    println!("end of handshake");
+    println!("end of handshake with {}", chan.remote_peer_id());
+    println!("negotiated {:?}", chan.network_version());
     tokio::time::sleep(Duration::from_secs(10)).await;
 
     Ok(())
 }
+
+async fn listen(addr: Option<String>, identity_path: PathBuf, peer: PeerArgs) -> Result<()> {
+    let addr = addr.unwrap_or_else(|| format!("0.0.0.0:{}", peer.network.default_port));
+    let mut listener = peer
+        .handshake(Identity::from_file(identity_path)?)?
+        .listen(addr)
+        .await?;
+    println!("listening on {}", listener.local_addr()?);
+    loop {
+        match listener.accept().await {
+            Ok((chan, peer)) => {
+                println!("end of handshake with {} ({})", chan.remote_peer_id(), peer);
+                tokio::spawn(async move {
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                    drop(chan);
+                });
+            }
+            Err(e) => eprintln!("{:#}", e),
+        }
+    }
+}
+
+fn describe(name: Option<String>, json: bool) -> Result<()> {
+    let Some(name) = name else {
+        for codec in registry::codecs() {
+            println!("{}", codec.name);
+        }
+        return Ok(());
+    };
+    let codec = registry::codec(&name)?;
+    let schema = (codec.schema)();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&schema)?);
+    } else {
+        print!("{}", schema.describe(codec.name));
+    }
+    Ok(())
+}
+
+fn codec(command: CodecCommand) -> Result<()> {
+    match command {
+        CodecCommand::Decode {
+            name,
+            input,
+            annotated,
+        } => {
+            let codec = registry::codec(&name)?;
+            let input = read_input(&input)?;
+            // hex, unless it is a raw binary file
+            let bytes = match std::str::from_utf8(&input).map(|text| hex::decode(text.trim())) {
+                Ok(Ok(bytes)) => bytes,
+                _ => input,
+            };
+            let json = if annotated {
+                let (json, span) = (codec.decode_spans)(&bytes);
+                println!("{}", span.annotate(&bytes));
+                json?
+            } else {
+                (codec.decode)(&bytes)?
+            };
+            println!("{}", serde_json::to_string_pretty(&json)?);
+        }
+        CodecCommand::Encode { name, input } => {
+            let codec = registry::codec(&name)?;
+            let json = serde_json::from_slice(&read_input(&input)?)?;
+            println!("{}", hex::encode((codec.encode)(json)?));
+        }
+    }
+    Ok(())
+}
+
+/// The argument itself, the content of a file with `@path`, or stdin with `-`
+fn read_input(arg: &str) -> Result<Vec<u8>> {
+    if arg == "-" {
+        let mut bytes = vec![];
+        std::io::stdin().read_to_end(&mut bytes)?;
+        Ok(bytes)
+    } else if let Some(path) = arg.strip_prefix('@') {
+        Ok(std::fs::read(path)?)
+    } else {
+        Ok(arg.as_bytes().to_vec())
+    }
+}
+
+async fn generate_identity(
+    difficulty: u8,
+    out: PathBuf,
+    threads: Option<usize>,
+    checkpoint: Option<PathBuf>,
+) -> Result<()> {
+    // fail early rather than after the proof of work
+    if out.exists() {
+        bail!("{} already exists", out.display());
+    }
+    let mut search = match &checkpoint {
+        Some(path) if path.exists() => {
+            println!("resuming from {}", path.display());
+            Search::resume(path)?
+        }
+        _ => {
+            println!("generating identity with difficulty {}", difficulty);
+            let mut rng = thread_rng();
+            Search::new(SecretKey::generate(&mut rng), difficulty, &mut rng)
+        }
+    };
+    if let Some(threads) = threads {
+        search = search.threads(threads);
+    }
+    if let Some(path) = checkpoint {
+        search = search.checkpoint(path);
+    }
+    let cancel = Arc::new(AtomicBool::new(false));
+    let search = search.cancel_on(cancel.clone()).on_progress(|p| {
+        eprint!(
+            "\r{} attempts, {:.0} attempts/s, eta {}s    ",
+            p.attempts,
+            p.rate,
+            p.eta.as_secs()
+        )
+    });
+
+    let mut task = tokio::task::spawn_blocking(move || search.run());
+    let res = tokio::select! {
+        res = &mut task => res?,
+        _ = tokio::signal::ctrl_c() => {
+            cancel.store(true, Ordering::Relaxed);
+            task.await?
+        }
+    };
+    eprintln!();
+    let identity = res?;
+    identity.to_file(&out)?;
+    println!("stored identity {} in {}", identity.peer_id, out.display());
+    Ok(())
+}
@@ -0,0 +1,213 @@
+/// Tezos networks we can connect to.
+///
+/// Built-in presets follow the octez `config_file.ml` networks, custom test networks are
+/// loaded from JSON or TOML files with the same fields:
+/// ```toml
+/// chain_name = "TEZOS_MY_TESTNET"
+/// bootstrap_peers = ["127.0.0.1:19732"]
+/// expected_pow = 0
+/// ```
+use std::{fs, path::Path, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    identity::pow::DEFAULT_DIFFICULTY,
+    p2p::{version::SupportedVersions, ChainName, DDBVersion, P2PVersion, DEFAULT_CHAIN},
+};
+
+pub const DEFAULT_PORT: u16 = 9732;
+
+#[derive(Debug, Error)]
+pub enum NetworkError {
+    #[error("Unknown network `{0}`, expected mainnet, ghostnet, sandbox or a .json/.toml file")]
+    Unknown(String),
+    #[error("A network needs at least one distributed db and one p2p version")]
+    NoVersion,
+    #[error("IO error `{0}`")]
+    IO(#[from] std::io::Error),
+    #[error("Invalid json network `{0}`")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid toml network `{0}`")]
+    Toml(#[from] toml::de::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Network {
+    pub chain_name: ChainName,
+    /// "host" or "host:port" of the nodes to connect to first
+    #[serde(default)]
+    pub bootstrap_peers: Vec<String>,
+    #[serde(default = "default_port")]
+    pub default_port: u16,
+    /// Minimum proof of work difficulty expected from the peers
+    #[serde(default = "default_expected_pow")]
+    pub expected_pow: u8,
+    #[serde(default = "default_distributed_db_versions")]
+    pub distributed_db_versions: Vec<DDBVersion>,
+    #[serde(default = "default_p2p_versions")]
+    pub p2p_versions: Vec<P2PVersion>,
+}
+
+fn default_port() -> u16 {
+    DEFAULT_PORT
+}
+fn default_expected_pow() -> u8 {
+    DEFAULT_DIFFICULTY
+}
+fn default_distributed_db_versions() -> Vec<DDBVersion> {
+    vec![DDBVersion(0), DDBVersion(1), DDBVersion(2)]
+}
+fn default_p2p_versions() -> Vec<P2PVersion> {
+    vec![P2PVersion(0), P2PVersion(1)]
+}
+
+impl Network {
+    fn preset(chain_name: &str, bootstrap_peers: &[&str], expected_pow: u8) -> Self {
+        Network {
+            chain_name: ChainName::from(chain_name),
+            bootstrap_peers: bootstrap_peers.iter().map(ToString::to_string).collect(),
+            default_port: DEFAULT_PORT,
+            expected_pow,
+            distributed_db_versions: default_distributed_db_versions(),
+            p2p_versions: default_p2p_versions(),
+        }
+    }
+
+    pub fn mainnet() -> Self {
+        Self::preset(
+            "TEZOS_MAINNET",
+            &[
+                "boot.tzinit.org",
+                "boot.tzbeta.net",
+                "boot.mainnet.oxheadhosted.com",
+            ],
+            DEFAULT_DIFFICULTY,
+        )
+    }
+
+    pub fn ghostnet() -> Self {
+        Self::preset(
+            DEFAULT_CHAIN,
+            &["ghostnet.tzinit.org", "ghostnet.boot.ecadinfra.com"],
+            DEFAULT_DIFFICULTY,
+        )
+    }
+
+    /// Local network of `octez-node --sandbox`, without proof of work.
+    pub fn sandbox() -> Self {
+        Self::preset("SANDBOXED_TEZOS", &[], 0)
+    }
+
+    /// Loads a custom network, the format is picked from the extension (`.json` or `.toml`).
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, NetworkError> {
+        let path = path.as_ref();
+        let network: Network = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(&fs::read_to_string(path)?)?,
+            Some("toml") => toml::from_str(&fs::read_to_string(path)?)?,
+            _ => return Err(NetworkError::Unknown(path.display().to_string())),
+        };
+        network.versions()?;
+        Ok(network)
+    }
+
+    /// Fails with `NetworkError::NoVersion` when a list of versions is empty.
+    pub fn versions(&self) -> Result<SupportedVersions, NetworkError> {
+        SupportedVersions::new(
+            self.chain_name.clone(),
+            self.distributed_db_versions.clone(),
+            self.p2p_versions.clone(),
+        )
+    }
+
+    /// Bootstrap peers as "host:port", using the default port when they don't have one.
+    pub fn bootstrap_addrs(&self) -> Vec<String> {
+        self.bootstrap_peers
+            .iter()
+            .map(|peer| {
+                if peer.ends_with(']') || !peer.contains(':') {
+                    format!("{}:{}", peer, self.default_port)
+                } else {
+                    peer.clone()
+                }
+            })
+            .collect()
+    }
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Self::ghostnet()
+    }
+}
+
+impl FromStr for Network {
+    type Err = NetworkError;
+
+    /// A preset name or the path of a custom network file.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mainnet" => Ok(Self::mainnet()),
+            "ghostnet" => Ok(Self::ghostnet()),
+            "sandbox" => Ok(Self::sandbox()),
+            path => Self::from_file(path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use anyhow::Result;
+
+    use crate::p2p::{ChainName, DDBVersion, P2PVersion};
+
+    use super::{Network, NetworkError};
+
+    #[test]
+    fn it_loads_custom_networks() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("tzhandhsake-network-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+
+        let toml_path = dir.join("testnet.toml");
+        write!(
+            std::fs::File::create(&toml_path)?,
+            r#"
+            chain_name = "TEZOS_TESTNET"
+            bootstrap_peers = ["127.0.0.1", "[::1]", "localhost:19732"]
+            expected_pow = 0
+            p2p_versions = [1]
+            "#
+        )?;
+        let network: Network = toml_path.to_str().unwrap().parse()?;
+        assert_eq!(ChainName::from("TEZOS_TESTNET"), network.chain_name);
+        assert_eq!(0, network.expected_pow);
+        assert_eq!(vec![P2PVersion(1)], network.p2p_versions);
+        assert_eq!(
+            vec![DDBVersion(0), DDBVersion(1), DDBVersion(2)],
+            network.distributed_db_versions
+        );
+        assert_eq!(
+            vec!["127.0.0.1:9732", "[::1]:9732", "localhost:19732"],
+            network.bootstrap_addrs()
+        );
+
+        let json_path = dir.join("testnet.json");
+        std::fs::write(&json_path, serde_json::to_string(&network)?)?;
+        assert_eq!(network, Network::from_file(&json_path)?);
+
+        std::fs::write(&json_path, r#"{"chain_name": "X", "p2p_versions": []}"#)?;
+        assert!(matches!(
+            Network::from_file(&json_path),
+            Err(NetworkError::NoVersion)
+        ));
+        assert!(matches!(
+            "devnet".parse::<Network>(),
+            Err(NetworkError::Unknown(_))
+        ));
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -1,19 +1,75 @@
-/// Ser/de for Tezos p2p messages
-///
-use serde::{Deserialize, Serialize};
+/// `TezosEncoding` of the p2p types that can't derive it, and their json through serde.
+/// Serde only writes json here, `bin` refuses these types.
+use std::net::SocketAddr;
+
+use serde::{de, ser, Deserialize, Serialize};
 
 use crate::p2p::{Nonce, PublicKey};
 
-use crate::encoding::bin::BuffVisitor;
+use crate::encoding::{
+    direct::{BinReader, BinWriter, Schema, TezosEncoding},
+    error::Result as EncodingResult,
+    json::{deserialize_hex, serialize_hex},
+};
+
+use super::{AckMessage, NackMotive};
+
+impl TezosEncoding for Nonce {
+    fn encode(&self, out: &mut BinWriter) -> EncodingResult<()> {
+        out.write(self.as_ref());
+        Ok(())
+    }
+
+    fn decode(input: &mut BinReader<'_>) -> EncodingResult<Self> {
+        input.read_array::<24>().map(Self::from)
+    }
+
+    fn schema() -> Schema {
+        Schema::Bytes { size: Some(24) }
+    }
+}
 
-use super::Metadata;
+impl TezosEncoding for PublicKey {
+    fn encode(&self, out: &mut BinWriter) -> EncodingResult<()> {
+        out.write(self.as_ref());
+        Ok(())
+    }
+
+    fn decode(input: &mut BinReader<'_>) -> EncodingResult<Self> {
+        input.read_array::<32>().map(Self::from)
+    }
+
+    fn schema() -> Schema {
+        Schema::Bytes { size: Some(32) }
+    }
+}
+
+impl TezosEncoding for NackMotive {
+    fn encode(&self, out: &mut BinWriter) -> EncodingResult<()> {
+        u16::from(*self).encode(out)
+    }
+
+    fn decode(input: &mut BinReader<'_>) -> EncodingResult<Self> {
+        u16::decode(input).map(Self::from)
+    }
+
+    fn schema() -> Schema {
+        u16::schema()
+    }
+}
+
+/// Error of the serde impls below in binary
+const JSON_ONLY: &str = "only json goes through serde, binary is TezosEncoding";
 
 impl<'de> Deserialize<'de> for Nonce {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        let bytes = deserializer.deserialize_seq(BuffVisitor::<24>)?;
+        if !deserializer.is_human_readable() {
+            return Err(de::Error::custom(JSON_ONLY));
+        }
+        let bytes = deserialize_hex::<_, 24>(deserializer)?;
         Ok(Self::from(bytes))
     }
 }
@@ -22,7 +78,10 @@ impl Serialize for Nonce {
     where
         S: serde::Serializer,
     {
-        serializer.serialize_bytes(self.as_ref())
+        if !serializer.is_human_readable() {
+            return Err(ser::Error::custom(JSON_ONLY));
+        }
+        serialize_hex(self.as_ref(), serializer)
     }
 }
 
@@ -31,7 +90,10 @@ impl<'de> Deserialize<'de> for PublicKey {
     where
         D: serde::Deserializer<'de>,
     {
-        let bytes = deserializer.deserialize_seq(BuffVisitor::<32>)?;
+        if !deserializer.is_human_readable() {
+            return Err(de::Error::custom(JSON_ONLY));
+        }
+        let bytes = deserialize_hex::<_, 32>(deserializer)?;
         Ok(Self::from(bytes))
     }
 }
@@ -40,35 +102,84 @@ impl Serialize for PublicKey {
     where
         S: serde::Serializer,
     {
-        serializer.serialize_bytes(self.as_ref())
+        if !serializer.is_human_readable() {
+            return Err(ser::Error::custom(JSON_ONLY));
+        }
+        serialize_hex(self.as_ref(), serializer)
     }
 }
 
-impl<'de> Deserialize<'de> for Metadata {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+/// Json of acks, octez `P2p_socket.Ack.encoding`, `{"ack": {}}` for the cases without fields
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum AckJson {
+    Ack {},
+    #[serde(rename = "nack_v_0")]
+    NackV0 {},
+    #[serde(untagged)]
+    Nack {
+        nack_motive: u16,
+        nack_list: Vec<SocketAddr>,
+    },
+}
+
+impl Serialize for AckMessage {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
-        D: serde::Deserializer<'de>,
+        S: serde::Serializer,
     {
-        let bytes = deserializer.deserialize_seq(BuffVisitor::<2>)?;
-        Ok(Metadata(bytes))
+        if !serializer.is_human_readable() {
+            return Err(ser::Error::custom(JSON_ONLY));
+        }
+        let json = match self {
+            AckMessage::Ack => AckJson::Ack {},
+            AckMessage::NackV0 => AckJson::NackV0 {},
+            AckMessage::Nack {
+                motive,
+                potential_peers_to_connect,
+            } => AckJson::Nack {
+                nack_motive: (*motive).into(),
+                nack_list: potential_peers_to_connect.clone(),
+            },
+        };
+        json.serialize(serializer)
     }
 }
-impl Serialize for Metadata {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+
+impl<'de> Deserialize<'de> for AckMessage {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
-        S: serde::Serializer,
+        D: serde::Deserializer<'de>,
     {
-        serializer.serialize_bytes(&self.0)
+        if !deserializer.is_human_readable() {
+            return Err(de::Error::custom(JSON_ONLY));
+        }
+        Ok(match AckJson::deserialize(deserializer)? {
+            AckJson::Ack {} => AckMessage::Ack,
+            AckJson::NackV0 {} => AckMessage::NackV0,
+            AckJson::Nack {
+                nack_motive,
+                nack_list,
+            } => AckMessage::Nack {
+                motive: nack_motive.into(),
+                potential_peers_to_connect: nack_list,
+            },
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
+    use bytes::BytesMut;
+    use tokio_util::codec::Decoder;
 
     use crate::{
-        encoding::{bin::to_bytes, read::Read},
-        p2p::ConnectionMessage,
+        encoding::{
+            bin,
+            direct::{self, from_bytes, to_bytes, to_bytes_no_header},
+        },
+        p2p::{frame::TezosFrameCodec, AckMessage, ConnectionMessage, Metadata, NackMotive},
     };
 
     #[test]
@@ -91,14 +202,14 @@ mod tests {
         assert_eq!(&res[36..60], (conn_msg.proof_of_work_stamp).as_ref());
         assert_eq!(&res[60..84], (conn_msg.nonce).as_ref());
         assert_eq!(&res[84..88], &[0, 0, 0, 36]);
-        assert_eq!(&res[88..124], (conn_msg.chain_name).as_ref());
+        assert_eq!(&res[88..124], (conn_msg.version.chain_name).as_ref());
         assert_eq!(&res[124..126], &[0, 2]);
         assert_eq!(&res[126..128], &[0, 1]);
 
         Ok(())
     }
-    #[tokio::test]
-    async fn it_deserializes_connection_message() -> Result<()> {
+    #[test]
+    fn it_deserializes_connection_message() -> Result<()> {
         let conn_msg = ConnectionMessage {
             port: 9732,
             public_key: [0xf2; 32].into(),
@@ -107,11 +218,112 @@ mod tests {
             ..Default::default()
         };
 
-        let res = to_bytes(&conn_msg)?;
-        let (deser, _) = ConnectionMessage::full_read_buffer(&mut res.as_ref())
-            .await
-            .unwrap();
-        assert_eq!(conn_msg, deser);
+        let mut res = BytesMut::from(&to_bytes(&conn_msg)?[..]);
+        let frame = TezosFrameCodec.decode(&mut res)?.expect("a whole frame");
+        assert_eq!(conn_msg, from_bytes(&frame)?);
+        assert!(res.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn it_serializes_metadata_booleans() -> Result<()> {
+        let metadata = Metadata {
+            disable_mempool: false,
+            private_node: true,
+        };
+        let res = to_bytes_no_header(&metadata)?;
+        assert_eq!(&[0x00, 0xff], &res[..]);
+        assert_eq!(metadata, from_bytes(&res)?);
+        assert!(from_bytes::<Metadata>(&[0x01, 0x00]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn it_serializes_acks() -> Result<()> {
+        assert_eq!(vec![0], to_bytes_no_header(&AckMessage::Ack)?);
+        assert_eq!(vec![0xff], to_bytes_no_header(&AckMessage::NackV0)?);
+        let nack = AckMessage::Nack {
+            motive: NackMotive::TooManyConnections,
+            potential_peers_to_connect: vec!["127.0.0.1:9732".parse()?, "[::1]:9733".parse()?],
+        };
+        let res = to_bytes_no_header(&nack)?;
+        // | tag | motive | size of the list | size of the point | point | ...
+        assert_eq!(&[1, 0, 1, 0, 0, 0, 32, 0, 0, 0, 14], &res[..11]);
+        assert_eq!(b"127.0.0.1:9732", &res[11..25]);
+        assert_eq!(&[0, 0, 0, 10], &res[25..29]);
+        assert_eq!(
+            nack,
+            from_bytes(&res)?,
+            "{}",
+            direct::annotated::<AckMessage>(&res)
+        );
+        assert_eq!(AckMessage::Ack, from_bytes(&[0])?);
+        assert!(from_bytes::<AckMessage>(&[2]).is_err());
+        assert_eq!(
+            AckMessage::Nack {
+                motive: NackMotive::Unknown(42),
+                potential_peers_to_connect: vec![]
+            },
+            from_bytes(&[1, 0, 42, 0, 0, 0, 0])?
+        );
+        assert!(from_bytes::<AckMessage>(&[1, 0, 42]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_octez_nacks() -> Result<()> {
+        // `P2p_socket.Ack.Nack {motive = Too_many_connections; potential_peers_to_connect =
+        // [127.0.0.1:9732]}` laid out after octez `P2p_socket.Ack.encoding`: | tag 0x01 | motive,
+        // uint16 | list prefixed by its size in bytes | point prefixed by its size |
+        let octez = hex::decode("010001000000120000000e3132372e302e302e313a39373332")?;
+        let nack = AckMessage::Nack {
+            motive: NackMotive::TooManyConnections,
+            potential_peers_to_connect: vec!["127.0.0.1:9732".parse()?],
+        };
+        assert_eq!(nack, from_bytes(&octez)?);
+        assert_eq!(octez, to_bytes_no_header(&nack)?);
+
+        // at most 100 points
+        let points = vec!["127.0.0.1:9732".parse()?; 101];
+        let nack = AckMessage::Nack {
+            motive: NackMotive::TooManyConnections,
+            potential_peers_to_connect: points,
+        };
+        assert!(to_bytes_no_header(&nack).is_err());
+        let mut bytes = vec![1, 0, 1];
+        bytes.extend((101 * 18u32).to_be_bytes());
+        for _ in 0..101 {
+            bytes.extend([0, 0, 0, 14]);
+            bytes.extend(b"127.0.0.1:9732");
+        }
+        assert!(matches!(
+            from_bytes::<AckMessage>(&bytes),
+            Err(direct::Error::ListTooLong { max: 100 })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn it_leaves_the_binary_encoding_to_tezos_encoding() -> Result<()> {
+        let conn_msg = ConnectionMessage {
+            port: 9732,
+            public_key: [0xf2; 32].into(),
+            proof_of_work_stamp: [1; 24].into(),
+            nonce: [2; 24].into(),
+            ..Default::default()
+        };
+        assert!(bin::to_bytes(&conn_msg).is_err());
+        assert!(bin::from_bytes::<ConnectionMessage>(&to_bytes_no_header(&conn_msg)?).is_err());
+        assert!(bin::to_bytes_no_header(&AckMessage::Ack).is_err());
+        assert!(bin::from_bytes::<AckMessage>(&[0]).is_err());
+        let metadata = Metadata {
+            disable_mempool: true,
+            private_node: false,
+        };
+        assert_eq!(
+            bin::to_bytes_no_header(&metadata)?,
+            to_bytes_no_header(&metadata)?
+        );
         Ok(())
     }
 }
@@ -0,0 +1,307 @@
+/// Framing of p2p connections, from the bytes on the wire up to messages.
+///
+/// Each layer is a `tokio_util` codec, a connection goes through them as the handshake
+/// progresses (see `Framed::map_codec`), without losing the bytes already buffered:
+/// - [`TezosFrameCodec`]: frames prefixed by their size on 2 bytes, the connection message
+/// - [`EncryptedCodec`]: frames encrypted with the channel key, metadata and acks
+/// - [`MessageCodec`]: messages prefixed by their size on 4 bytes, split in encrypted frames
+///
+/// Decoding the messages themselves is left to `encoding::direct`.
+use std::{io, sync::Arc};
+
+use bytes::{Buf, BufMut, BytesMut};
+use crypto_box::{aead::AeadInPlace, SalsaBox};
+use tokio_util::codec::{Decoder, Encoder};
+
+use super::{
+    handshake::{HandhshakeError, P2PError},
+    Nonce,
+};
+
+const TAG_LENGTH: usize = 16;
+/// Most bytes of a message encrypted in a single frame, octez `binary_chunks_size`
+pub const MAX_CHUNK_SIZE: usize = u16::MAX as usize - TAG_LENGTH;
+/// Messages are prefixed by their size, octez `dynamic_size`
+const MESSAGE_PREFIX: usize = 4;
+
+/// | size (u16) | payload |, decodes to the payload.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TezosFrameCodec;
+
+impl Decoder for TezosFrameCodec {
+    type Item = BytesMut;
+    type Error = P2PError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, P2PError> {
+        if src.len() < 2 {
+            return Ok(None);
+        }
+        let size = u16::from_be_bytes([src[0], src[1]]) as usize;
+        if src.len() < 2 + size {
+            src.reserve(2 + size - src.len());
+            return Ok(None);
+        }
+        src.advance(2);
+        Ok(Some(src.split_to(size)))
+    }
+}
+
+impl Encoder<&[u8]> for TezosFrameCodec {
+    type Error = P2PError;
+
+    fn encode(&mut self, payload: &[u8], dst: &mut BytesMut) -> Result<(), P2PError> {
+        let size = u16::try_from(payload.len()).map_err(|_| P2PError::MessageTooBig {
+            size: payload.len(),
+            max: u16::MAX as usize,
+        })?;
+        dst.reserve(2 + payload.len());
+        dst.put_u16(size);
+        dst.put_slice(payload);
+        Ok(())
+    }
+}
+
+/// | size (u16) | tag | encrypted |, decodes to the decrypted bytes.
+///
+/// Each direction has its own nonce, incremented after every frame. Clones share the key
+/// but not the nonces, the halves of a channel each keep a clone.
+#[derive(Clone)]
+pub struct EncryptedCodec {
+    channel_key: Arc<SalsaBox>,
+    local_nonce: Nonce,
+    remote_nonce: Nonce,
+}
+
+impl EncryptedCodec {
+    /// `local_nonce` encrypts the frames we send, `remote_nonce` decrypts the ones we receive.
+    pub fn new(channel_key: SalsaBox, local_nonce: Nonce, remote_nonce: Nonce) -> Self {
+        EncryptedCodec {
+            channel_key: Arc::new(channel_key),
+            local_nonce,
+            remote_nonce,
+        }
+    }
+}
+
+impl Decoder for EncryptedCodec {
+    type Item = BytesMut;
+    type Error = P2PError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, P2PError> {
+        let Some(mut encrypted) = TezosFrameCodec.decode(src)? else {
+            return Ok(None);
+        };
+        if encrypted.len() < TAG_LENGTH {
+            return Err(HandhshakeError::EncryptedMessageShorterThanTag.into());
+        }
+        let mut tag = [0; TAG_LENGTH];
+        tag.copy_from_slice(&encrypted.split_to(TAG_LENGTH));
+        self.channel_key
+            .decrypt_in_place_detached(&self.remote_nonce.0, &[0; 0], &mut encrypted, &tag.into())
+            .map_err(|s| P2PError::Crypto(s.to_string()))?;
+        self.remote_nonce.inc();
+        Ok(Some(encrypted))
+    }
+}
+
+impl Encoder<&[u8]> for EncryptedCodec {
+    type Error = P2PError;
+
+    fn encode(&mut self, chunk: &[u8], dst: &mut BytesMut) -> Result<(), P2PError> {
+        if chunk.len() > MAX_CHUNK_SIZE {
+            return Err(P2PError::MessageTooBig {
+                size: chunk.len(),
+                max: MAX_CHUNK_SIZE,
+            });
+        }
+        let mut buffer = chunk.to_vec();
+        let tag = self
+            .channel_key
+            .encrypt_in_place_detached(&self.local_nonce.0, &[0; 0], &mut buffer)
+            .map_err(|s| P2PError::Crypto(s.to_string()))?;
+        dst.reserve(2 + TAG_LENGTH + buffer.len());
+        dst.put_u16((TAG_LENGTH + buffer.len()) as u16);
+        dst.put_slice(&tag);
+        dst.put_slice(&buffer);
+        // the nonce moves on before the frame is written, if writing fails the
+        // best for the node is to close the channel, and redo a handshake
+        self.local_nonce.inc();
+        Ok(())
+    }
+}
+
+/// | size (u30) | message |, split in as many encrypted frames as needed like octez
+/// `P2p_socket.Reader` and `Writer`, decodes to the bytes of the message.
+#[derive(Clone)]
+pub struct MessageCodec {
+    chunks: EncryptedCodec,
+    /// Decrypted bytes not decoded yet, a chunk may end in the middle of a message
+    read_buffer: BytesMut,
+    max_message_size: usize,
+}
+
+impl MessageCodec {
+    /// Messages bigger than `max_message_size`, without their size prefix, are refused both
+    /// ways.
+    pub fn new(chunks: EncryptedCodec, max_message_size: usize) -> Self {
+        MessageCodec {
+            chunks,
+            read_buffer: BytesMut::new(),
+            max_message_size,
+        }
+    }
+}
+
+impl Decoder for MessageCodec {
+    type Item = BytesMut;
+    type Error = P2PError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, P2PError> {
+        loop {
+            if self.read_buffer.len() >= MESSAGE_PREFIX {
+                let size = u32::from_be_bytes([
+                    self.read_buffer[0],
+                    self.read_buffer[1],
+                    self.read_buffer[2],
+                    self.read_buffer[3],
+                ]) as usize;
+                // before buffering anything, the peer may announce anything
+                if size > self.max_message_size {
+                    return Err(P2PError::MessageTooBig {
+                        size,
+                        max: self.max_message_size,
+                    });
+                }
+                if self.read_buffer.len() >= MESSAGE_PREFIX + size {
+                    self.read_buffer.advance(MESSAGE_PREFIX);
+                    return Ok(Some(self.read_buffer.split_to(size)));
+                }
+            }
+            match self.chunks.decode(src)? {
+                Some(chunk) => self.read_buffer.extend_from_slice(&chunk),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, P2PError> {
+        match self.decode(src)? {
+            Some(message) => Ok(Some(message)),
+            None if src.is_empty() && self.read_buffer.is_empty() => Ok(None),
+            None => Err(P2PError::Network(io::ErrorKind::UnexpectedEof.into())),
+        }
+    }
+}
+
+impl Encoder<&[u8]> for MessageCodec {
+    type Error = P2PError;
+
+    fn encode(&mut self, message: &[u8], dst: &mut BytesMut) -> Result<(), P2PError> {
+        if message.len() > self.max_message_size {
+            return Err(P2PError::MessageTooBig {
+                size: message.len(),
+                max: self.max_message_size,
+            });
+        }
+        let mut bytes = Vec::with_capacity(MESSAGE_PREFIX + message.len());
+        bytes.put_u32(message.len() as u32);
+        bytes.put_slice(message);
+        for chunk in bytes.chunks(MAX_CHUNK_SIZE) {
+            self.chunks.encode(chunk, dst)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use bytes::BytesMut;
+    use crypto_box::{aead::OsRng, SalsaBox, SecretKey};
+    use futures::{SinkExt, StreamExt};
+    use tokio_util::codec::{Decoder, Encoder, Framed};
+
+    use crate::p2p::{handshake::P2PError, Nonce};
+
+    use super::{EncryptedCodec, MessageCodec, TezosFrameCodec, MAX_CHUNK_SIZE};
+
+    /// Codecs of both ends of a connection
+    fn codecs() -> (EncryptedCodec, EncryptedCodec) {
+        let (a, b) = (
+            SecretKey::generate(&mut OsRng),
+            SecretKey::generate(&mut OsRng),
+        );
+        let (a_to_b, b_to_a) = (Nonce::from([1; 24]), Nonce::from([2; 24]));
+        (
+            EncryptedCodec::new(
+                SalsaBox::new(&b.public_key(), &a),
+                a_to_b.clone(),
+                b_to_a.clone(),
+            ),
+            EncryptedCodec::new(SalsaBox::new(&a.public_key(), &b), b_to_a, a_to_b),
+        )
+    }
+
+    #[test]
+    fn it_waits_for_whole_frames() -> Result<()> {
+        let mut wire = BytesMut::new();
+        TezosFrameCodec.encode(&b"tezos"[..], &mut wire)?;
+        assert_eq!(&[0, 5], &wire[..2]);
+        let mut src = BytesMut::new();
+        for byte in &wire[..wire.len() - 1] {
+            src.extend_from_slice(&[*byte]);
+            assert_eq!(None, TezosFrameCodec.decode(&mut src)?);
+        }
+        src.extend_from_slice(&wire[wire.len() - 1..]);
+        assert_eq!(
+            Some(&b"tezos"[..]),
+            TezosFrameCodec.decode(&mut src)?.as_deref()
+        );
+        assert!(src.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn it_decrypts_frames_in_order() -> Result<()> {
+        let (mut local, mut remote) = codecs();
+        let mut wire = BytesMut::new();
+        local.encode(&b"first"[..], &mut wire)?;
+        local.encode(&b"second"[..], &mut wire)?;
+        // | size | tag | encrypted |
+        assert_eq!(&[0, 16 + 5], &wire[..2]);
+        assert_eq!(Some(&b"first"[..]), remote.decode(&mut wire)?.as_deref());
+        assert_eq!(Some(&b"second"[..]), remote.decode(&mut wire)?.as_deref());
+
+        // a frame decrypted with the wrong nonce, here our own, is refused
+        local.encode(&b"third"[..], &mut wire)?;
+        assert!(matches!(local.decode(&mut wire), Err(P2PError::Crypto(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_splits_messages_over_frames() -> Result<()> {
+        let (local, remote) = codecs();
+        // room for everything, the writes don't wait for the reads
+        let (a, b) = tokio::io::duplex(1 << 20);
+        let mut local = Framed::new(a, MessageCodec::new(local, 200_000));
+        let mut remote = Framed::new(b, MessageCodec::new(remote, 140_000));
+
+        let big: Vec<u8> = (0..MAX_CHUNK_SIZE * 2).map(|i| i as u8).collect();
+        local.send(&big[..]).await?;
+        local.send(&b"small"[..]).await?;
+        local.send(&[0; 150_000][..]).await?;
+        assert_eq!(Some(&big[..]), remote.next().await.transpose()?.as_deref());
+        assert_eq!(
+            Some(&b"small"[..]),
+            remote.next().await.transpose()?.as_deref()
+        );
+        assert!(matches!(
+            remote.next().await,
+            Some(Err(P2PError::MessageTooBig {
+                size: 150_000,
+                max: 140_000
+            }))
+        ));
+        Ok(())
+    }
+}
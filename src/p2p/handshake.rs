@@ -1,30 +1,45 @@
-use std::marker::PhantomData;
+use std::{
+    marker::PhantomData,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
 
 /// Handshake module
 ///
 use crate::{
     encoding::{
         self,
-        bin::{to_bytes, to_bytes_no_header},
+        direct::{from_bytes, to_bytes, to_bytes_no_header, TezosEncoding},
+    },
+    identity::{
+        pow::{check_proof_of_work, DEFAULT_DIFFICULTY},
+        CryptoboxPublicKeyHash, Identity,
+    },
+    network::{Network, NetworkError},
+    p2p::{
+        frame::{EncryptedCodec, MessageCodec, TezosFrameCodec},
+        message::PeerMessage,
+        version::{NetworkVersion, SupportedVersions},
+        AckMessage, ConnectionMessage, NackMotive, PublicKey,
     },
-    identity::Identity,
-    p2p::{Ack, ConnectionMessage, PublicKey},
 };
 
-use anyhow::Result;
+use anyhow::{Context as _, Result};
 use async_trait::async_trait;
 use blake2::digest::{consts::U32, Digest};
 use blake2::Blake2b;
-use crypto_box::aead::AeadMutInPlace;
-use crypto_box::{aead::rand_core::CryptoRngCore, SalsaBox};
-use serde::{Deserialize, Serialize};
+use bytes::BytesMut;
+use crypto_box::aead::{rand_core::CryptoRngCore, OsRng};
+use futures::{Sink, SinkExt, Stream, StreamExt};
 use thiserror::Error;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpStream, ToSocketAddrs},
+    io::{self, AsyncRead, AsyncWrite, ReadHalf, WriteHalf},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    task::JoinSet,
 };
-
-use crate::encoding::read::Read;
+use tokio_util::codec::{Framed, FramedRead, FramedWrite};
 
 use super::{Metadata, Nonce};
 
@@ -34,6 +49,23 @@ pub enum HandhshakeError {
     MissingNonce,
     #[error("The encrypted message must at least be longer than a tag")]
     EncryptedMessageShorterThanTag,
+    #[error("Peer {peer_id} has not enough proof of work for difficulty {difficulty}")]
+    NotEnoughProofOfWork {
+        peer_id: CryptoboxPublicKeyHash,
+        difficulty: u8,
+    },
+    #[error("The peer refused the connection: {motive}, suggested peers: {suggested_peers:?}")]
+    Nacked {
+        motive: NackMotive,
+        suggested_peers: Vec<SocketAddr>,
+    },
+    #[error("No version in common with the peer ({motive}), it announced {remote:?}")]
+    IncompatibleVersion {
+        motive: NackMotive,
+        remote: NetworkVersion,
+    },
+    #[error("The handshake didn't complete within {0:?}")]
+    Timeout(Duration),
 }
 
 #[derive(Debug, Error)]
@@ -48,12 +80,25 @@ pub enum P2PError {
     Serde(#[from] encoding::error::Error),
     #[error("Anyhow: `{0}`")]
     Anyhow(#[from] anyhow::Error),
+    #[error("Message of {size} bytes exceeds the maximum of {max} bytes")]
+    MessageTooBig { size: usize, max: usize },
 }
 
-#[derive(Debug)]
+/// Largest message we accept or send by default, octez messages stay well below
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 2 * 1024 * 1024;
+/// Time given to a peer to complete the handshake, octez `authentication_timeout`
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
 pub struct Handshake {
     identity: Identity,
     nonce: Option<Nonce>,
+    port: u16,
+    pow_difficulty: u8,
+    metadata: Metadata,
+    versions: SupportedVersions,
+    max_message_size: usize,
+    timeout: Duration,
 }
 
 /// Kind of Builder pattern
@@ -62,6 +107,12 @@ impl Handshake {
         Self {
             identity,
             nonce: None,
+            port: 0,
+            pow_difficulty: DEFAULT_DIFFICULTY,
+            metadata: Metadata::default(),
+            versions: SupportedVersions::default(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            timeout: DEFAULT_HANDSHAKE_TIMEOUT,
         }
     }
     pub fn generate_nonce<R>(mut self, rng: &mut R) -> Self
@@ -75,194 +126,501 @@ impl Handshake {
         self.nonce = Some(nonce);
         self
     }
+    /// Minimum proof of work difficulty expected from the remote peer.
+    pub fn with_pow_difficulty(mut self, difficulty: u8) -> Self {
+        self.pow_difficulty = difficulty;
+        self
+    }
+    /// Metadata sent to the peer, to advertise a private node or a disabled mempool.
+    pub fn with_metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+    /// Chain, versions and proof of work difficulty of a network.
+    pub fn with_network(self, network: &Network) -> Result<Self, NetworkError> {
+        Ok(self
+            .with_versions(network.versions()?)
+            .with_pow_difficulty(network.expected_pow))
+    }
+    /// Chain and versions we speak, peers without a version in common are nacked.
+    pub fn with_versions(mut self, versions: SupportedVersions) -> Self {
+        self.versions = versions;
+        self
+    }
+    /// Advertised listening port, 0 when we don't accept connections.
+    pub fn with_listening_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+    /// Size of the largest message the channel reads or writes, without its size prefix.
+    pub fn with_max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+    /// Time after which a handshake that isn't complete fails, so that a silent peer can't
+    /// hold a connection, or the `Listener`, forever.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
     pub async fn connect<A>(self, peer: A) -> Result<Channel<TcpStream>>
     where
         A: ToSocketAddrs,
     {
-        let mut stream = TcpStream::connect(peer).await?;
+        let stream = TcpStream::connect(peer).await?;
+        self.perform(stream, ConnectionDirection::Outgoing).await
+    }
+
+    /// Responder side of the handshake on an already accepted `stream`.
+    pub async fn accept<S>(self, stream: S) -> Result<Channel<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        self.perform(stream, ConnectionDirection::Incoming).await
+    }
+
+    /// Binds `addr` and returns a `Listener` performing the responder side of the handshake
+    /// for each incoming connection. The nonce of this builder is ignored, a fresh one is
+    /// generated per connection.
+    pub async fn listen<A>(mut self, addr: A) -> Result<Listener>
+    where
+        A: ToSocketAddrs,
+    {
+        let listener = TcpListener::bind(addr).await?;
+        if self.port == 0 {
+            self.port = listener.local_addr()?.port();
+        }
+        Ok(Listener {
+            listener,
+            handshake: self,
+            pending: JoinSet::new(),
+        })
+    }
+
+    async fn perform<S>(self, stream: S, direction: ConnectionDirection) -> Result<Channel<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let timeout = self.timeout;
+        tokio::time::timeout(timeout, self.exchange(stream, direction))
+            .await
+            .map_err(|_| HandhshakeError::Timeout(timeout))?
+    }
+
+    async fn exchange<S>(self, stream: S, direction: ConnectionDirection) -> Result<Channel<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
         let nonce = self.nonce.ok_or(HandhshakeError::MissingNonce)?;
 
         let sent = ConnectionMessage {
+            port: self.port,
             public_key: PublicKey::new(self.identity.public_key.clone()),
             nonce,
             proof_of_work_stamp: Nonce::from(self.identity.proof_of_work_stamp.bytes()),
-            ..Default::default()
+            version: self.versions.announced(),
         };
+        // nonces are computed from the messages with their size, as sent on the wire, the
+        // codec adds the size back when sending
         let sent_bytes = to_bytes(&sent)?;
+        let mut frames = Framed::new(stream, TezosFrameCodec);
 
-        let (received, received_bytes) = ConnectionMessage::full_read_buffer(&mut stream).await?;
+        // the responder speaks first, the initiator answers
+        if let ConnectionDirection::Incoming = direction {
+            frames.send(&sent_bytes[2..]).await?;
+        }
+        let received_frame = frames.next().await.ok_or_else(unexpected_eof)??;
+        let received: ConnectionMessage = from_bytes(&received_frame)?;
+        check_remote_pow(&received, self.pow_difficulty)?;
+        // like octez, an incompatible peer still gets our metadata and is nacked afterwards
+        let selected = self.versions.select(&received.version);
 
+        let size = (received_frame.len() as u16).to_be_bytes();
+        let received_bytes = [&size[..], &received_frame].concat();
         let received = ReceivedMsg::new(received, received_bytes);
 
-        stream.write_all(&sent_bytes).await?;
-        stream.flush().await?;
+        if let ConnectionDirection::Outgoing = direction {
+            frames.send(&sent_bytes[2..]).await?;
+        }
         let sent = SentMsg::new(sent, sent_bytes.to_vec());
-        // this form of builder pattern is not great to be honest.
-        let mut chan = Channel::new(
-            stream,
-            &self.identity,
-            received,
-            sent,
-            ConnectionDirection::Outgoing,
-        );
+        let remote_peer_id = CryptoboxPublicKeyHash::from(received.value.public_key());
+        let remote_version = received.value.version.clone();
+        let codec = encrypted_codec(&self.identity, received, sent, &direction);
+        let mut chunks = frames.map_codec(|_| codec);
         // these are messages that seem to be exchanged to verify that we can encrypt/decrypt
         // correctly. Not sure why we don't that with Acks.
-        let _metadata = chan.read_metadata().await?;
-        println!("received metadata: {:?}", _metadata);
-        chan.write_metadata().await?;
+        let (remote_metadata, network_version) = match direction {
+            ConnectionDirection::Outgoing => {
+                let remote_metadata: Metadata = read_chunk(&mut chunks).await?;
+                write_chunk(&mut chunks, &self.metadata).await?;
+
+                check_ack(read_chunk(&mut chunks).await?)?;
+                let version = write_ack(&mut chunks, selected, remote_version).await?;
+                (remote_metadata, version)
+            }
+            ConnectionDirection::Incoming => {
+                write_chunk(&mut chunks, &self.metadata).await?;
+                let remote_metadata: Metadata = read_chunk(&mut chunks).await?;
 
-        let ack = chan.read::<Ack>().await?;
-        println!("received ack: {:?}", ack);
-        chan.write(Ack(true)).await?;
+                let version = write_ack(&mut chunks, selected, remote_version).await?;
+                check_ack(read_chunk(&mut chunks).await?)?;
+                (remote_metadata, version)
+            }
+        };
 
-        Ok(chan)
+        Ok(Channel {
+            framed: chunks.map_codec(|codec| MessageCodec::new(codec, self.max_message_size)),
+            remote_peer_id,
+            remote_metadata,
+            network_version,
+        })
     }
 }
 
-pub struct Channel<S> {
-    stream: S,
-    channel_key: SalsaBox,
-    local_nonce: Nonce,
-    remote_nonce: Nonce,
+/// Accepts incoming connections and handshakes them.
+pub struct Listener {
+    listener: TcpListener,
+    handshake: Handshake,
+    /// Handshakes in progress, with the address of their peer
+    pending: JoinSet<(SocketAddr, Result<Channel<TcpStream>>)>,
 }
 
-impl<S> Channel<S> {
-    fn new(
-        stream: S,
-        identity: &Identity,
-        received: Msg<ConnectionMessage, Received>,
-        sent: Msg<ConnectionMessage, Sent>,
-        direction: ConnectionDirection,
-    ) -> Self {
-        let channel_key =
-            crypto_box::SalsaBox::new(received.value.public_key(), &identity.secret_key);
-
-        // reorder the bytes to be fully deterministic before nonce computation
-        let (init_bytes, resp_bytes) = match direction {
-            ConnectionDirection::Incoming => (received.bytes, sent.bytes),
-            ConnectionDirection::Outgoing => (sent.bytes, received.bytes),
-        };
-        let init_resp_nonce = compute_nonce(&init_bytes, &resp_bytes, b"Init -> Resp");
-        let resp_init_nonce = compute_nonce(&init_bytes, &resp_bytes, b"Resp -> Init");
-        let (local_nonce, remote_nonce) = match direction {
-            ConnectionDirection::Incoming => (init_resp_nonce, resp_init_nonce),
-            ConnectionDirection::Outgoing => (resp_init_nonce, init_resp_nonce),
-        };
+impl Listener {
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
 
-        Channel {
-            stream,
-            channel_key,
-            local_nonce,
-            remote_nonce,
+    /// Waits for the next peer done with the responder side of the handshake. Handshakes run
+    /// concurrently, each within the timeout of the `Handshake`, so a silent peer doesn't hold
+    /// back the others. A failed handshake errors with the address of its peer.
+    pub async fn accept(&mut self) -> Result<(Channel<TcpStream>, SocketAddr)> {
+        loop {
+            tokio::select! {
+                accepted = self.listener.accept() => {
+                    let (stream, addr) = accepted?;
+                    let handshake = self.handshake.clone().generate_nonce(&mut OsRng);
+                    self.pending
+                        .spawn(async move { (addr, handshake.accept(stream).await) });
+                }
+                Some(done) = self.pending.join_next() => {
+                    let (addr, chan) = done?;
+                    let chan = chan.with_context(|| format!("handshake with {} failed", addr))?;
+                    return Ok((chan, addr));
+                }
+            }
         }
     }
 }
 
-impl<S> Channel<S>
-where
-    S: AsyncWriteExt + Send + Unpin,
-{
-    async fn write_metadata(&mut self) -> Result<(), P2PError>
+/// Encrypted connection with a peer, once the handshake is done.
+pub struct Channel<S> {
+    framed: Framed<S, MessageCodec>,
+    remote_peer_id: CryptoboxPublicKeyHash,
+    remote_metadata: Metadata,
+    network_version: NetworkVersion,
+}
+
+impl<S> Channel<S> {
+    /// Peer id of the remote node, derived from the public key it sent during the handshake.
+    pub fn remote_peer_id(&self) -> &CryptoboxPublicKeyHash {
+        &self.remote_peer_id
+    }
+
+    /// Metadata sent by the remote node during the handshake, tells whether it is a private
+    /// node and if it accepts operations.
+    pub fn remote_metadata(&self) -> &Metadata {
+        &self.remote_metadata
+    }
+
+    /// Version negotiated with the remote node.
+    pub fn network_version(&self) -> &NetworkVersion {
+        &self.network_version
+    }
+
+    /// Splits the channel in halves that can be moved to different tasks, a reader loop and a
+    /// writer loop. Each half keeps its own nonce, they only share the key.
+    pub fn into_split(self) -> (ChannelReader<ReadHalf<S>>, ChannelWriter<WriteHalf<S>>)
     where
-        S: AsyncWriteExt + Unpin,
+        S: AsyncRead + AsyncWrite,
     {
-        self.write(&Metadata::default()).await
+        let parts = self.framed.into_parts();
+        let (read, write) = tokio::io::split(parts.io);
+        let mut reader = FramedRead::new(read, parts.codec.clone());
+        *reader.read_buffer_mut() = parts.read_buf;
+        let mut writer = FramedWrite::new(write, parts.codec);
+        *writer.write_buffer_mut() = parts.write_buf;
+        (
+            ChannelReader { framed: reader },
+            ChannelWriter { framed: writer },
+        )
     }
 }
-impl<S> Channel<S>
+
+/// Reading half of a [`Channel`], see [`Channel::into_split`].
+pub struct ChannelReader<R> {
+    framed: FramedRead<R, MessageCodec>,
+}
+
+/// Writing half of a [`Channel`], see [`Channel::into_split`].
+pub struct ChannelWriter<W> {
+    framed: FramedWrite<W, MessageCodec>,
+}
+
+fn unexpected_eof() -> P2PError {
+    P2PError::Network(io::ErrorKind::UnexpectedEof.into())
+}
+
+/// Handshake messages, each in a single chunk without the size prefix of messages.
+async fn read_chunk<S, T>(chunks: &mut Framed<S, EncryptedCodec>) -> Result<T, P2PError>
 where
-    S: AsyncReadExt + Send + Unpin,
+    S: AsyncRead + Unpin,
+    T: TezosEncoding,
 {
-    async fn read_metadata(&mut self) -> Result<Metadata, P2PError>
-    where
-        S: AsyncReadExt + Unpin,
-    {
-        self.read::<Metadata>().await
+    let chunk = chunks.next().await.ok_or_else(unexpected_eof)??;
+    Ok(from_bytes(&chunk)?)
+}
+
+async fn write_chunk<S, T>(
+    chunks: &mut Framed<S, EncryptedCodec>,
+    value: &T,
+) -> Result<(), P2PError>
+where
+    S: AsyncWrite + Unpin,
+    T: TezosEncoding,
+{
+    chunks.send(&to_bytes_no_header(value)?[..]).await
+}
+
+/// Acks the peer when we have a version in common, nacks it otherwise. Returns the version
+/// in use once acked.
+async fn write_ack<S>(
+    chunks: &mut Framed<S, EncryptedCodec>,
+    selected: Result<NetworkVersion, NackMotive>,
+    remote: NetworkVersion,
+) -> Result<NetworkVersion>
+where
+    S: AsyncWrite + Unpin,
+{
+    match selected {
+        Ok(version) => {
+            write_chunk(chunks, &AckMessage::Ack).await?;
+            Ok(version)
+        }
+        Err(motive) => {
+            let nack = AckMessage::Nack {
+                motive,
+                potential_peers_to_connect: vec![],
+            };
+            write_chunk(chunks, &nack).await?;
+            Err(HandhshakeError::IncompatibleVersion { motive, remote })?
+        }
     }
 }
-const TAG_LENGTH: u16 = 16;
 
+/// Messages exchanged once the handshake is done: prefixed by their size on 4 bytes and split
+/// in as many encrypted frames as needed, see `frame::MessageCodec`.
 #[async_trait]
 pub trait TezosRead {
     async fn read<T>(&mut self) -> Result<T, P2PError>
     where
-        T: Send + for<'de> Deserialize<'de>;
+        T: Send + TezosEncoding;
 }
 
 #[async_trait]
 pub trait TezosWrite {
     async fn write<T>(&mut self, value: T) -> Result<(), P2PError>
     where
-        T: Send + Serialize;
+        T: Send + TezosEncoding;
 }
 
-impl<S> Channel<S> {
-    fn inc_local(&mut self) {
-        self.local_nonce.inc()
+#[async_trait]
+impl<S> TezosRead for Channel<S>
+where
+    S: AsyncRead + Unpin + Send,
+{
+    async fn read<T>(&mut self) -> Result<T, P2PError>
+    where
+        T: Send + TezosEncoding,
+    {
+        let message = self.framed.next().await.ok_or_else(unexpected_eof)??;
+        Ok(from_bytes(&message)?)
     }
-    fn inc_remote(&mut self) {
-        self.remote_nonce.inc()
+}
+#[async_trait]
+impl<S> TezosWrite for Channel<S>
+where
+    S: AsyncWrite + Unpin + Send,
+{
+    async fn write<T>(&mut self, value: T) -> Result<(), P2PError>
+    where
+        T: Send + TezosEncoding,
+    {
+        self.framed.send(&to_bytes_no_header(&value)?[..]).await
     }
 }
 
 #[async_trait]
-impl<S> TezosRead for Channel<S>
+impl<R> TezosRead for ChannelReader<R>
 where
-    S: AsyncReadExt + Unpin + Send,
+    R: AsyncRead + Unpin + Send,
 {
     async fn read<T>(&mut self) -> Result<T, P2PError>
     where
-        T: Send + for<'de> Deserialize<'de>,
+        T: Send + TezosEncoding,
     {
-        let mut header = self.stream.read_u16().await?;
-        if header < TAG_LENGTH {
-            return Err(HandhshakeError::EncryptedMessageShorterThanTag.into());
-        }
-        let mut tag = [0; TAG_LENGTH as usize];
-        self.stream.read_exact(&mut tag).await?;
-        header -= TAG_LENGTH;
-
-        let mut encrypted = vec![0; header as usize];
-        self.stream.read_exact(&mut encrypted).await?;
-        self.channel_key
-            .decrypt_in_place_detached(&self.remote_nonce.0, &[0; 0], &mut encrypted, &tag.into())
-            .map_err(|s| P2PError::Crypto(s.to_string()))?;
-        let recv = T::read(&mut encrypted.as_ref(), header as usize).await?;
-        self.inc_remote();
-        Ok(recv)
+        let message = self.framed.next().await.ok_or_else(unexpected_eof)??;
+        Ok(from_bytes(&message)?)
     }
 }
 #[async_trait]
-impl<S> TezosWrite for Channel<S>
+impl<W> TezosWrite for ChannelWriter<W>
 where
-    S: AsyncWriteExt + Unpin + Send,
+    W: AsyncWrite + Unpin + Send,
 {
     async fn write<T>(&mut self, value: T) -> Result<(), P2PError>
     where
-        T: Send + Serialize,
+        T: Send + TezosEncoding,
     {
-        let mut buffer = to_bytes_no_header(&value)?;
-        let tag = self
-            .channel_key
-            .encrypt_in_place_detached(&self.local_nonce.0, &[0; 0], &mut buffer)
-            .map_err(|s| P2PError::Crypto(s.to_string()))?;
-        let size = tag.len() + buffer.len();
-        // is this  a programming error?
-        assert!(
-            size <= u16::max_value() as usize,
-            "breaking protocol with msg too big",
-        );
-        self.stream.write_u16(size as u16).await?;
-        self.stream.write_all(&tag).await?;
-        self.stream.write_all(&buffer).await?;
-        self.stream.flush().await?;
-        // what happened if we fail before this incremnt.
-        // The best for the node is to close the channel, and redo a handshake
-        self.inc_local();
+        self.framed.send(&to_bytes_no_header(&value)?[..]).await
+    }
+}
+
+fn decode_message(message: Result<BytesMut, P2PError>) -> Result<PeerMessage, P2PError> {
+    Ok(from_bytes(&message?)?)
+}
+
+/// Peer messages until the peer closes the connection.
+impl<S> Stream for Channel<S>
+where
+    S: AsyncRead + Unpin,
+{
+    type Item = Result<PeerMessage, P2PError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.framed
+            .poll_next_unpin(cx)
+            .map(|message| message.map(decode_message))
+    }
+}
+
+/// Sent peer messages are buffered until the sink is flushed, or the buffer is full.
+impl<S> Sink<PeerMessage> for Channel<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    type Error = P2PError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), P2PError>> {
+        SinkExt::<&[u8]>::poll_ready_unpin(&mut self.framed, cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, message: PeerMessage) -> Result<(), P2PError> {
+        self.framed
+            .start_send_unpin(&to_bytes_no_header(&message)?[..])
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), P2PError>> {
+        SinkExt::<&[u8]>::poll_flush_unpin(&mut self.framed, cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), P2PError>> {
+        SinkExt::<&[u8]>::poll_close_unpin(&mut self.framed, cx)
+    }
+}
+
+impl<R> Stream for ChannelReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    type Item = Result<PeerMessage, P2PError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.framed
+            .poll_next_unpin(cx)
+            .map(|message| message.map(decode_message))
+    }
+}
+
+impl<W> Sink<PeerMessage> for ChannelWriter<W>
+where
+    W: AsyncWrite + Unpin,
+{
+    type Error = P2PError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), P2PError>> {
+        SinkExt::<&[u8]>::poll_ready_unpin(&mut self.framed, cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, message: PeerMessage) -> Result<(), P2PError> {
+        self.framed
+            .start_send_unpin(&to_bytes_no_header(&message)?[..])
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), P2PError>> {
+        SinkExt::<&[u8]>::poll_flush_unpin(&mut self.framed, cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), P2PError>> {
+        SinkExt::<&[u8]>::poll_close_unpin(&mut self.framed, cx)
+    }
+}
+
+/// Same check as octez `p2p_socket.ml`, the connection is refused before sending anything
+/// encrypted to the peer.
+fn check_remote_pow(received: &ConnectionMessage, difficulty: u8) -> Result<(), HandhshakeError> {
+    let pk = received.public_key();
+    if check_proof_of_work(pk, received.proof_of_work_stamp.as_ref(), difficulty) {
         Ok(())
+    } else {
+        Err(HandhshakeError::NotEnoughProofOfWork {
+            peer_id: CryptoboxPublicKeyHash::from(pk),
+            difficulty,
+        })
     }
 }
 
+/// Turns a refusal of the peer into an error, older nodes don't tell why they refuse.
+fn check_ack(ack: AckMessage) -> Result<(), HandhshakeError> {
+    match ack {
+        AckMessage::Ack => Ok(()),
+        AckMessage::NackV0 => Err(HandhshakeError::Nacked {
+            motive: NackMotive::NoMotive,
+            suggested_peers: vec![],
+        }),
+        AckMessage::Nack {
+            motive,
+            potential_peers_to_connect,
+        } => Err(HandhshakeError::Nacked {
+            motive,
+            suggested_peers: potential_peers_to_connect,
+        }),
+    }
+}
+
+/// Codec of the frames encrypted with the channel key, the key and the nonces of both
+/// directions are derived from the connection messages.
+fn encrypted_codec(
+    identity: &Identity,
+    received: Msg<ConnectionMessage, Received>,
+    sent: Msg<ConnectionMessage, Sent>,
+    direction: &ConnectionDirection,
+) -> EncryptedCodec {
+    let channel_key = crypto_box::SalsaBox::new(received.value.public_key(), &identity.secret_key);
+
+    // reorder the bytes to be fully deterministic before nonce computation
+    let (init_bytes, resp_bytes) = match direction {
+        ConnectionDirection::Incoming => (received.bytes, sent.bytes),
+        ConnectionDirection::Outgoing => (sent.bytes, received.bytes),
+    };
+    let init_resp_nonce = compute_nonce(&init_bytes, &resp_bytes, b"Init -> Resp");
+    let resp_init_nonce = compute_nonce(&init_bytes, &resp_bytes, b"Resp -> Init");
+    let (local_nonce, remote_nonce) = match direction {
+        ConnectionDirection::Incoming => (init_resp_nonce, resp_init_nonce),
+        ConnectionDirection::Outgoing => (resp_init_nonce, init_resp_nonce),
+    };
+    EncryptedCodec::new(channel_key, local_nonce, remote_nonce)
+}
+
 fn compute_nonce(sent: &[u8], recv: &[u8], seed: &[u8]) -> Nonce {
     type Blake2b256 = Blake2b<U32>;
     let res = Blake2b256::digest([sent, recv, seed].concat());
@@ -272,7 +630,6 @@ fn compute_nonce(sent: &[u8], recv: &[u8], seed: &[u8]) -> Nonce {
 }
 
 enum ConnectionDirection {
-    #[allow(dead_code)]
     Incoming,
     Outgoing,
 }
@@ -296,3 +653,290 @@ type ReceivedMsg<A> = Msg<A, Received>;
 
 struct Sent;
 struct Received;
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use futures::{stream, SinkExt, StreamExt, TryStreamExt};
+    use rand::thread_rng;
+    use tokio::{net::TcpStream, time::timeout};
+
+    use crate::{
+        encoding::direct::Bytes,
+        identity::Identity,
+        p2p::{
+            message::PeerMessage, version::SupportedVersions, AckMessage, ChainName,
+            ConnectionMessage, DDBVersion, Metadata, NackMotive, P2PVersion,
+        },
+    };
+
+    use super::{
+        check_ack, check_remote_pow, Channel, HandhshakeError, Handshake, Nonce, P2PError,
+        PublicKey, TezosRead, TezosWrite, DEFAULT_MAX_MESSAGE_SIZE,
+    };
+
+    #[tokio::test]
+    async fn it_times_out_silent_peers() -> Result<()> {
+        let mut rng = thread_rng();
+        let mut listener = Handshake::identity(Identity::generate(4, &mut rng)?)
+            .with_timeout(Duration::from_millis(100))
+            .listen("127.0.0.1:0")
+            .await?;
+        // connects but never sends its connection message
+        let _silent = TcpStream::connect(listener.local_addr()?).await?;
+        let err = listener.accept().await.err().expect("a timeout");
+        assert!(matches!(
+            err.downcast_ref::<HandhshakeError>(),
+            Some(HandhshakeError::Timeout(_))
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_handshakes_other_peers_while_one_is_silent() -> Result<()> {
+        let mut rng = thread_rng();
+        let (responder, initiator) = (
+            Identity::generate(4, &mut rng)?,
+            Identity::generate(4, &mut rng)?,
+        );
+        let initiator_id = initiator.peer_id;
+        let mut listener = Handshake::identity(responder)
+            .with_pow_difficulty(4)
+            .with_timeout(Duration::from_secs(1))
+            .listen("127.0.0.1:0")
+            .await?;
+        let addr = listener.local_addr()?;
+        let silent = TcpStream::connect(addr).await?;
+        let connect = Handshake::identity(initiator)
+            .generate_nonce(&mut rng)
+            .with_pow_difficulty(4)
+            .connect(addr);
+
+        let (accepted, connected) = tokio::join!(listener.accept(), connect);
+        let (responder, _) = accepted?;
+        connected?;
+        assert_eq!(&initiator_id, responder.remote_peer_id());
+
+        let err = listener.accept().await.err().expect("a timeout");
+        assert!(err.to_string().contains(&silent.local_addr()?.to_string()));
+        assert!(matches!(
+            err.downcast_ref::<HandhshakeError>(),
+            Some(HandhshakeError::Timeout(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn it_checks_remote_proof_of_work() {
+        let identity = Identity::generate(8, &mut thread_rng()).unwrap();
+        let received = ConnectionMessage {
+            public_key: PublicKey::new(identity.public_key.clone()),
+            proof_of_work_stamp: Nonce::from(identity.proof_of_work_stamp.bytes()),
+            ..Default::default()
+        };
+        assert!(check_remote_pow(&received, 8).is_ok());
+        let err = check_remote_pow(&received, 64).unwrap_err();
+        assert!(matches!(
+            err,
+            HandhshakeError::NotEnoughProofOfWork { peer_id, difficulty: 64 } if peer_id == identity.peer_id
+        ));
+    }
+
+    #[test]
+    fn it_reports_nack_motives() -> Result<()> {
+        assert!(check_ack(AckMessage::Ack).is_ok());
+        assert!(matches!(
+            check_ack(AckMessage::NackV0),
+            Err(HandhshakeError::Nacked { motive: NackMotive::NoMotive, suggested_peers }) if suggested_peers.is_empty()
+        ));
+        let peer = "10.0.0.1:9732".parse()?;
+        let err = check_ack(AckMessage::Nack {
+            motive: NackMotive::TooManyConnections,
+            potential_peers_to_connect: vec![peer],
+        })
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            HandhshakeError::Nacked { motive: NackMotive::TooManyConnections, suggested_peers } if suggested_peers == vec![peer]
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_handshakes_a_listener() -> Result<()> {
+        let mut rng = thread_rng();
+        let responder = Identity::generate(4, &mut rng)?;
+        let initiator = Identity::generate(4, &mut rng)?;
+        let (responder_id, initiator_id) = (responder.peer_id, initiator.peer_id);
+        let private = Metadata {
+            disable_mempool: true,
+            private_node: true,
+        };
+        let mut listener = Handshake::identity(responder)
+            .with_pow_difficulty(4)
+            .with_metadata(private)
+            .listen("127.0.0.1:0")
+            .await?;
+        let addr = listener.local_addr()?;
+        let connect = Handshake::identity(initiator)
+            .generate_nonce(&mut rng)
+            .with_pow_difficulty(4)
+            .connect(addr);
+
+        let (accepted, connected) = tokio::join!(listener.accept(), connect);
+        let (mut responder, _) = accepted?;
+        let mut initiator = connected?;
+        assert_eq!(&initiator_id, responder.remote_peer_id());
+        assert_eq!(&responder_id, initiator.remote_peer_id());
+        assert_eq!(&private, initiator.remote_metadata());
+        assert_eq!(&Metadata::default(), responder.remote_metadata());
+        assert_eq!(
+            &SupportedVersions::default().announced(),
+            initiator.network_version()
+        );
+
+        // both directions must be able to decrypt each other
+        initiator.write(ChainName::default()).await?;
+        assert_eq!(ChainName::default(), responder.read::<ChainName>().await?);
+        responder.write(ChainName::default()).await?;
+        assert_eq!(ChainName::default(), initiator.read::<ChainName>().await?);
+        Ok(())
+    }
+
+    /// Responder and initiator sides of a local connection.
+    async fn channels(max_message_size: usize) -> Result<(Channel<TcpStream>, Channel<TcpStream>)> {
+        let mut rng = thread_rng();
+        let mut listener = Handshake::identity(Identity::generate(4, &mut rng)?)
+            .with_pow_difficulty(4)
+            .with_max_message_size(max_message_size)
+            .listen("127.0.0.1:0")
+            .await?;
+        let addr = listener.local_addr()?;
+        let connect = Handshake::identity(Identity::generate(4, &mut rng)?)
+            .generate_nonce(&mut rng)
+            .with_pow_difficulty(4)
+            .connect(addr);
+        let (accepted, connected) = tokio::join!(listener.accept(), connect);
+        Ok((accepted?.0, connected?))
+    }
+
+    #[tokio::test]
+    async fn it_splits_messages_in_chunks() -> Result<()> {
+        let (mut responder, mut initiator) = channels(150_000).await?;
+
+        // 3 chunks, the last one also holds the next message
+        let big = Bytes((0..140_000).map(|i| i as u8).collect());
+        initiator.write(big.clone()).await?;
+        initiator.write(ChainName::default()).await?;
+        assert_eq!(big, responder.read::<Bytes>().await?);
+        assert_eq!(ChainName::default(), responder.read::<ChainName>().await?);
+
+        let too_big = Bytes(vec![0; 200_000]);
+        assert!(matches!(
+            responder.write(too_big.clone()).await,
+            Err(P2PError::MessageTooBig {
+                size: 200_000,
+                max: 150_000
+            })
+        ));
+        initiator.write(too_big).await?;
+        assert!(matches!(
+            responder.read::<Bytes>().await,
+            Err(P2PError::MessageTooBig {
+                size: 200_000,
+                max: 150_000
+            })
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_reads_and_writes_from_separate_tasks() -> Result<()> {
+        let (responder, initiator) = channels(DEFAULT_MAX_MESSAGE_SIZE).await?;
+        let (mut responder_reader, mut responder_writer) = responder.into_split();
+        let (mut initiator_reader, mut initiator_writer) = initiator.into_split();
+
+        // both sides write before reading anything
+        let echo = tokio::spawn(async move {
+            for i in 0..10u32 {
+                responder_writer.write(i).await?;
+            }
+            Ok::<_, P2PError>(())
+        });
+        for i in 0..10u32 {
+            initiator_writer.write(i + 100).await?;
+        }
+        for i in 0..10u32 {
+            assert_eq!(i, initiator_reader.read::<u32>().await?);
+            assert_eq!(i + 100, responder_reader.read::<u32>().await?);
+        }
+        echo.await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_streams_peer_messages() -> Result<()> {
+        let (responder, mut initiator) = channels(DEFAULT_MAX_MESSAGE_SIZE).await?;
+        let (mut reader, _writer) = responder.into_split();
+
+        // nothing to read yet, cancelling the read doesn't lose the next message
+        assert!(timeout(Duration::from_millis(10), reader.next())
+            .await
+            .is_err());
+        let messages = vec![
+            PeerMessage::Bootstrap,
+            PeerMessage::Advertise {
+                points: vec!["127.0.0.1:9732".parse()?],
+            },
+            PeerMessage::Disconnect,
+        ];
+        initiator
+            .send_all(&mut stream::iter(messages.clone()).map(Ok))
+            .await?;
+        initiator.close().await?;
+        // the stream ends when the peer closes the connection
+        assert_eq!(messages, reader.try_collect::<Vec<_>>().await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_nacks_peers_on_another_chain() -> Result<()> {
+        let mut rng = thread_rng();
+        let mut listener = Handshake::identity(Identity::generate(4, &mut rng)?)
+            .with_pow_difficulty(4)
+            .listen("127.0.0.1:0")
+            .await?;
+        let addr = listener.local_addr()?;
+        let mainnet = SupportedVersions::new(
+            ChainName::from("TEZOS_MAINNET"),
+            vec![DDBVersion(2)],
+            vec![P2PVersion(1)],
+        )?;
+        let connect = Handshake::identity(Identity::generate(4, &mut rng)?)
+            .generate_nonce(&mut rng)
+            .with_pow_difficulty(4)
+            .with_versions(mainnet)
+            .connect(addr);
+
+        let (accepted, connected) = tokio::join!(listener.accept(), connect);
+        let accepted = accepted.err().expect("the responder refuses");
+        assert!(matches!(
+            accepted.downcast_ref::<HandhshakeError>(),
+            Some(HandhshakeError::IncompatibleVersion {
+                motive: NackMotive::UnknownChainName,
+                ..
+            })
+        ));
+        let connected = connected.err().expect("the initiator is nacked");
+        assert!(matches!(
+            connected.downcast_ref::<HandhshakeError>(),
+            Some(HandhshakeError::Nacked {
+                motive: NackMotive::UnknownChainName,
+                ..
+            })
+        ));
+        Ok(())
+    }
+}
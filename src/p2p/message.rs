@@ -0,0 +1,596 @@
+/// Messages peers exchange once the handshake is done.
+///
+/// `PeerMessage` merges octez `P2p_message` (`src/lib_p2p/p2p_message.ml`) with the messages of
+/// the distributed db (`src/lib_shell/distributed_db_message.ml`), they share a union tagged by a
+/// u16. In json the `kind` field names the case like octez does for `P2p_message`, the other
+/// fields keep the octez names.
+use std::net::SocketAddr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    encoding::{
+        direct::{BinReader, BinWriter, Bytes, Case, Error, Field, Result, Schema, TezosEncoding},
+        dynamic::Dynamic,
+        json,
+    },
+    hash::{
+        BlockHash, ChainId, ContextHash, CryptoboxPublicKeyHash, OperationHash,
+        OperationListListHash, ProtocolHash,
+    },
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TezosEncoding)]
+#[serde(tag = "kind")]
+#[encoding(tag_size = "u16")]
+pub enum PeerMessage {
+    #[encoding(tag = 0x01)]
+    Disconnect,
+    /// Asks for points to connect to
+    #[encoding(tag = 0x02)]
+    Bootstrap,
+    /// Points to connect to, until the end of the message
+    #[encoding(tag = 0x03)]
+    Advertise {
+        #[serde(rename = "id")]
+        #[encoding(max_length = 100)]
+        points: Vec<SocketAddr>,
+    },
+    #[serde(rename = "Swap_request")]
+    #[encoding(tag = 0x04)]
+    SwapRequest {
+        point: SocketAddr,
+        peer_id: CryptoboxPublicKeyHash,
+    },
+    #[serde(rename = "Swap_ack")]
+    #[encoding(tag = 0x05)]
+    SwapAck {
+        point: SocketAddr,
+        peer_id: CryptoboxPublicKeyHash,
+    },
+    #[serde(rename = "Get_current_branch")]
+    #[encoding(tag = 0x10)]
+    GetCurrentBranch {
+        #[serde(rename = "get_current_branch")]
+        chain_id: ChainId,
+    },
+    #[serde(rename = "Current_branch")]
+    #[encoding(tag = 0x11)]
+    CurrentBranch {
+        chain_id: ChainId,
+        current_branch: BlockLocator,
+    },
+    #[encoding(tag = 0x12)]
+    Deactivate {
+        #[serde(rename = "deactivate")]
+        chain_id: ChainId,
+    },
+    #[serde(rename = "Get_current_head")]
+    #[encoding(tag = 0x13)]
+    GetCurrentHead {
+        #[serde(rename = "get_current_head")]
+        chain_id: ChainId,
+    },
+    #[serde(rename = "Current_head")]
+    #[encoding(tag = 0x14)]
+    CurrentHead {
+        chain_id: ChainId,
+        #[encoding(dynamic)]
+        current_block_header: BlockHeader,
+        current_mempool: Mempool,
+    },
+    #[serde(rename = "Get_block_headers")]
+    #[encoding(tag = 0x20)]
+    GetBlockHeaders {
+        #[serde(rename = "get_block_headers")]
+        #[encoding(dynamic, max_length = 10)]
+        hashes: Vec<BlockHash>,
+    },
+    #[serde(rename = "Block_header")]
+    #[encoding(tag = 0x21)]
+    BlockHeader { block_header: BlockHeader },
+    #[serde(rename = "Get_operations")]
+    #[encoding(tag = 0x30)]
+    GetOperations {
+        #[serde(rename = "get_operations")]
+        #[encoding(dynamic, max_length = 10)]
+        hashes: Vec<OperationHash>,
+    },
+    #[encoding(tag = 0x31)]
+    Operation { operation: Operation },
+    #[serde(rename = "Get_protocols")]
+    #[encoding(tag = 0x40)]
+    GetProtocols {
+        #[serde(rename = "get_protocols")]
+        #[encoding(dynamic, max_length = 10)]
+        hashes: Vec<ProtocolHash>,
+    },
+    #[encoding(tag = 0x41)]
+    Protocol { protocol: Protocol },
+    #[serde(rename = "Get_operations_for_blocks")]
+    #[encoding(tag = 0x50)]
+    GetOperationsForBlocks {
+        #[serde(rename = "get_operations_for_blocks")]
+        #[encoding(dynamic, max_length = 10)]
+        blocks: Vec<OperationsForBlock>,
+    },
+    #[serde(rename = "Operations_for_blocks")]
+    #[encoding(tag = 0x51)]
+    OperationsForBlocks {
+        operations_for_block: OperationsForBlock,
+        operation_hashes_path: Path,
+        #[encoding(dynamic)]
+        operations: Vec<Dynamic<Operation>>,
+    },
+    #[serde(rename = "Get_checkpoint")]
+    #[encoding(tag = 0x60)]
+    GetCheckpoint {
+        #[serde(rename = "get_checkpoint")]
+        chain_id: ChainId,
+    },
+    #[encoding(tag = 0x61)]
+    Checkpoint {
+        chain_id: ChainId,
+        checkpoint: BlockHeader,
+    },
+    #[serde(rename = "Get_protocol_branch")]
+    #[encoding(tag = 0x62)]
+    GetProtocolBranch { chain_id: ChainId, proto_level: u8 },
+    #[serde(rename = "Protocol_branch")]
+    #[encoding(tag = 0x63)]
+    ProtocolBranch {
+        chain_id: ChainId,
+        proto_level: u8,
+        locator: BlockLocator,
+    },
+    #[serde(rename = "Get_predecessor_header")]
+    #[encoding(tag = 0x64)]
+    GetPredecessorHeader { block_hash: BlockHash, offset: i32 },
+    #[serde(rename = "Predecessor_header")]
+    #[encoding(tag = 0x65)]
+    PredecessorHeader {
+        block_hash: BlockHash,
+        offset: i32,
+        #[encoding(dynamic)]
+        header: BlockHeader,
+    },
+}
+
+/// Shell header followed by the protocol data, octez `Block_header.encoding`
+/// `src/lib_base/block_header.ml`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TezosEncoding)]
+pub struct BlockHeader {
+    pub level: i32,
+    pub proto: u8,
+    pub predecessor: BlockHash,
+    /// Seconds since the epoch
+    #[serde(with = "json::timestamp")]
+    pub timestamp: i64,
+    pub validation_pass: u8,
+    pub operations_hash: OperationListListHash,
+    #[encoding(dynamic)]
+    pub fitness: Vec<Dynamic<Bytes>>,
+    pub context: ContextHash,
+    /// Until the end of the header
+    pub protocol_data: Bytes,
+}
+
+/// Current head and some of its predecessors, octez `Block_locator.encoding`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TezosEncoding)]
+pub struct BlockLocator {
+    #[encoding(dynamic)]
+    pub current_head: BlockHeader,
+    /// Until the end of the message
+    pub history: Vec<BlockHash>,
+}
+
+/// Operations of the peer's mempool, octez `Mempool.encoding`
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize, TezosEncoding)]
+pub struct Mempool {
+    #[encoding(dynamic)]
+    pub known_valid: Vec<OperationHash>,
+    #[encoding(dynamic)]
+    pub pending: Vec<OperationHash>,
+}
+
+/// Branch followed by the protocol data, octez `Operation.encoding`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TezosEncoding)]
+pub struct Operation {
+    pub branch: BlockHash,
+    /// Until the end of the operation
+    pub data: Bytes,
+}
+
+/// Sources of a protocol, octez `Protocol.encoding`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TezosEncoding)]
+pub struct Protocol {
+    pub expected_env_version: u16,
+    #[encoding(dynamic)]
+    pub components: Vec<Component>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TezosEncoding)]
+pub struct Component {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    pub implementation: String,
+}
+
+/// Operations of a validation pass of a block
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TezosEncoding)]
+pub struct OperationsForBlock {
+    pub hash: BlockHash,
+    pub validation_pass: i8,
+}
+
+/// Path from the operations of a validation pass to the `operations_hash` of the block,
+/// octez `Operation_list_list_hash.path`.
+/// In json the cases are told apart by their fields, `Op` is `{}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Path {
+    Left {
+        path: Box<Path>,
+        right: OperationListListHash,
+    },
+    Right {
+        left: OperationListListHash,
+        path: Box<Path>,
+    },
+    Op {},
+}
+
+const PATH_LEFT: u8 = 0xf0;
+const PATH_RIGHT: u8 = 0x0f;
+const PATH_OP: u8 = 0x00;
+/// Blocks have a handful of validation passes, deeper paths are only good at exhausting the stack
+const MAX_PATH_DEPTH: usize = 32;
+
+/// Written by hand since the derive doesn't handle recursive types
+impl TezosEncoding for Path {
+    fn encode(&self, out: &mut BinWriter) -> Result<()> {
+        match self {
+            Path::Left { path, right } => {
+                PATH_LEFT.encode(out)?;
+                path.encode(out)?;
+                right.encode(out)
+            }
+            Path::Right { left, path } => {
+                PATH_RIGHT.encode(out)?;
+                left.encode(out)?;
+                path.encode(out)
+            }
+            Path::Op {} => PATH_OP.encode(out),
+        }
+    }
+
+    /// Iterative, the hash of a `Left` comes after the whole path it contains
+    fn decode(input: &mut BinReader<'_>) -> Result<Self> {
+        let mut steps = vec![];
+        loop {
+            match u8::decode(input)? {
+                PATH_LEFT => steps.push(None),
+                PATH_RIGHT => steps.push(Some(OperationListListHash::decode(input)?)),
+                PATH_OP => break,
+                tag => {
+                    return Err(Error::UnknownTag {
+                        name: "Path",
+                        tag: tag.into(),
+                    })
+                }
+            }
+            if steps.len() > MAX_PATH_DEPTH {
+                return Err(Error::Custom(format!(
+                    "operations path deeper than {}",
+                    MAX_PATH_DEPTH
+                )));
+            }
+        }
+        let mut path = Path::Op {};
+        while let Some(step) = steps.pop() {
+            path = match step {
+                None => Path::Left {
+                    path: Box::new(path),
+                    right: OperationListListHash::decode(input)?,
+                },
+                Some(left) => Path::Right {
+                    left,
+                    path: Box::new(path),
+                },
+            };
+        }
+        Ok(path)
+    }
+
+    fn schema() -> Schema {
+        let path = || Field {
+            name: Some("path"),
+            schema: Schema::Ref { name: "Path" },
+        };
+        let hash = |name| Field {
+            name: Some(name),
+            schema: OperationListListHash::schema(),
+        };
+        Schema::Union {
+            name: "Path",
+            tag_size: 1,
+            cases: vec![
+                Case {
+                    tag: PATH_LEFT.into(),
+                    name: "Left",
+                    fields: vec![path(), hash("right")],
+                },
+                Case {
+                    tag: PATH_RIGHT.into(),
+                    name: "Right",
+                    fields: vec![hash("left"), path()],
+                },
+                Case {
+                    tag: PATH_OP.into(),
+                    name: "Op",
+                    fields: vec![],
+                },
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use serde_json::{json, Value};
+
+    use crate::encoding::{
+        direct::{annotated, from_bytes, to_bytes_no_header, Bytes, Error},
+        dynamic::Dynamic,
+    };
+
+    use super::{BlockHeader, Mempool, Operation, OperationsForBlock, Path, PeerMessage};
+
+    const GENESIS: &str = "BLockGenesisGenesisGenesisGenesisGenesisf79b5d1CoW2";
+    const GENESIS_HEX: &str = "8fcf233671b6a04fcf679d2a381c2544ea6c1ea29ba6157776ed8424c7ccd00b";
+
+    /// `message` is encoded as `hex` and `json`, and decoded back from both
+    fn golden(message: PeerMessage, hex: &str, json: Value) -> Result<()> {
+        let bytes = to_bytes_no_header(&message)?;
+        let expected = hex::decode(hex)?;
+        assert_eq!(
+            hex,
+            hex::encode(&bytes),
+            "expected\n{}",
+            annotated::<PeerMessage>(&expected)
+        );
+        assert_eq!(
+            message,
+            from_bytes(&bytes)?,
+            "{}",
+            annotated::<PeerMessage>(&bytes)
+        );
+        assert_eq!(json, serde_json::to_value(&message)?);
+        assert_eq!(message, serde_json::from_value(json)?);
+        Ok(())
+    }
+
+    fn header() -> BlockHeader {
+        BlockHeader {
+            level: 2,
+            proto: 1,
+            predecessor: [0x11; 32].into(),
+            timestamp: 1_700_000_000,
+            validation_pass: 4,
+            operations_hash: [0x33; 32].into(),
+            fitness: vec![Dynamic(Bytes(vec![2])), Dynamic(Bytes(vec![0, 0, 0, 1]))],
+            context: [0x44; 32].into(),
+            protocol_data: Bytes(vec![0xab, 0xcd]),
+        }
+    }
+
+    #[test]
+    fn it_encodes_p2p_messages() -> Result<()> {
+        golden(
+            PeerMessage::Disconnect,
+            "0001",
+            json!({"kind": "Disconnect"}),
+        )?;
+        golden(PeerMessage::Bootstrap, "0002", json!({"kind": "Bootstrap"}))?;
+        golden(
+            PeerMessage::Advertise {
+                points: vec!["127.0.0.1:9732".parse()?],
+            },
+            // `Advertise` of octez `p2p_message.ml`: | tag | points until the end, each a
+            // `P2p_point.Id` string prefixed by its size |
+            &["0003", "0000000e", &hex::encode("127.0.0.1:9732")].concat(),
+            json!({"kind": "Advertise", "id": ["127.0.0.1:9732"]}),
+        )?;
+        golden(
+            PeerMessage::SwapAck {
+                point: "127.0.0.1:9732".parse()?,
+                peer_id: "idrpbo9Ru5pYiWTg1i2VPABG6Catfm".parse()?,
+            },
+            // | tag | point | `P2p_peer.Id`, 16 bytes |
+            &[
+                "0005",
+                "0000000e",
+                &hex::encode("127.0.0.1:9732"),
+                "642b3a0baa3bcb183c84c4b768b0819f",
+            ]
+            .concat(),
+            json!({
+                "kind": "Swap_ack",
+                "point": "127.0.0.1:9732",
+                "peer_id": "idrpbo9Ru5pYiWTg1i2VPABG6Catfm"
+            }),
+        )
+    }
+
+    #[test]
+    fn it_encodes_distributed_db_messages() -> Result<()> {
+        golden(
+            PeerMessage::GetCurrentBranch {
+                chain_id: "NetXdQprcVkpaWU".parse()?,
+            },
+            "00107a06a770",
+            json!({"kind": "Get_current_branch", "get_current_branch": "NetXdQprcVkpaWU"}),
+        )?;
+        golden(
+            PeerMessage::GetBlockHeaders {
+                hashes: vec![GENESIS.parse()?],
+            },
+            // `Get_block_headers` of octez `distributed_db_message.ml`: | tag | list prefixed by
+            // its size in bytes | 32 bytes hashes |
+            &["0020", "00000020", GENESIS_HEX].concat(),
+            json!({"kind": "Get_block_headers", "get_block_headers": [GENESIS]}),
+        )?;
+        golden(
+            PeerMessage::GetPredecessorHeader {
+                block_hash: GENESIS.parse()?,
+                offset: -1,
+            },
+            &format!("0064{}ffffffff", GENESIS_HEX),
+            json!({"kind": "Get_predecessor_header", "block_hash": GENESIS, "offset": -1}),
+        )?;
+        golden(
+            PeerMessage::Operation {
+                operation: Operation {
+                    branch: GENESIS.parse()?,
+                    data: Bytes(vec![0x6c, 0x00]),
+                },
+            },
+            &format!("0031{}6c00", GENESIS_HEX),
+            json!({"kind": "Operation", "operation": {"branch": GENESIS, "data": "6c00"}}),
+        )
+    }
+
+    #[test]
+    fn it_bounds_lists_like_octez() -> Result<()> {
+        let hashes = vec![GENESIS.parse()?; 11];
+        assert!(matches!(
+            to_bytes_no_header(&PeerMessage::GetBlockHeaders {
+                hashes: hashes.clone()
+            }),
+            Err(Error::ListTooLong { max: 10 })
+        ));
+        // | tag | size of the list | 11 hashes |
+        let too_long = hex::decode(format!("0020{:08x}{}", 11 * 32, GENESIS_HEX.repeat(11)))?;
+        assert!(matches!(
+            from_bytes::<PeerMessage>(&too_long),
+            Err(Error::ListTooLong { max: 10 })
+        ));
+
+        let points = vec!["127.0.0.1:9732".parse()?; 101];
+        assert!(matches!(
+            to_bytes_no_header(&PeerMessage::Advertise { points }),
+            Err(Error::ListTooLong { max: 100 })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn it_encodes_block_headers() -> Result<()> {
+        // octez `Block_header.encoding` (`src/lib_base/block_header.ml`), the shell header then
+        // the protocol data until the end
+        let header_hex = [
+            "00000002",         // level, int32
+            "01",               // proto, uint8
+            &"11".repeat(32),   // predecessor
+            "000000006553f100", // timestamp, int64 seconds
+            "04",               // validation_pass, uint8
+            &"33".repeat(32),   // operations_hash
+            "0000000d",         // fitness, size of the list in bytes
+            "0000000102",       // fitness element, prefixed by its size
+            "0000000400000001", // fitness element
+            &"44".repeat(32),   // context
+            "abcd",             // protocol_data
+        ]
+        .concat();
+        let header_json = json!({
+            "level": 2,
+            "proto": 1,
+            "predecessor": "BKqoHEY3C15u8zdGwi9Hhj3ArCz2Q8sRQuHVtcWZqUPopsfNZfh",
+            "timestamp": "2023-11-14T22:13:20Z",
+            "validation_pass": 4,
+            "operations_hash": "LLoZiFrAFbmXeJVpBeZmUNYCmZ16d7dfF3uXUsQxpQuHwVPAegxyC",
+            "fitness": ["02", "00000001"],
+            "context": "CoVANdsZzctZe9atEJDxsH7HNjsKF8YR1haGzS5x2HWtrgi57Viz",
+            "protocol_data": "abcd"
+        });
+        golden(
+            PeerMessage::BlockHeader {
+                block_header: header(),
+            },
+            &format!("0021{}", header_hex),
+            json!({"kind": "Block_header", "block_header": header_json}),
+        )?;
+        golden(
+            PeerMessage::CurrentHead {
+                chain_id: "NetXdQprcVkpaWU".parse()?,
+                current_block_header: header(),
+                current_mempool: Mempool {
+                    known_valid: vec![],
+                    pending: vec![[0x22; 32].into()],
+                },
+            },
+            &format!(
+                "00147a06a770{:08x}{}0000000000000020{}",
+                header_hex.len() / 2,
+                header_hex,
+                "22".repeat(32)
+            ),
+            json!({
+                "kind": "Current_head",
+                "chain_id": "NetXdQprcVkpaWU",
+                "current_block_header": header_json,
+                "current_mempool": {
+                    "known_valid": [],
+                    "pending": ["onuFAL9z4rhSSsXfzZQxNajNzPKArY2qbLoy54ZN3517ZnrUqpH"]
+                }
+            }),
+        )
+    }
+
+    #[test]
+    fn it_encodes_operations_paths() -> Result<()> {
+        let path = Path::Right {
+            left: [0x33; 32].into(),
+            path: Box::new(Path::Left {
+                path: Box::new(Path::Op {}),
+                right: [0x33; 32].into(),
+            }),
+        };
+        let llo = "LLoZiFrAFbmXeJVpBeZmUNYCmZ16d7dfF3uXUsQxpQuHwVPAegxyC";
+        golden(
+            PeerMessage::OperationsForBlocks {
+                operations_for_block: OperationsForBlock {
+                    hash: GENESIS.parse()?,
+                    validation_pass: 3,
+                },
+                operation_hashes_path: path,
+                operations: vec![],
+            },
+            // octez `Operation_list_list_hash.path_encoding`, steps tagged 0xf0 for `Left`,
+            // 0x0f for `Right` and 0x00 for `Op`
+            &[
+                "0051",
+                GENESIS_HEX,      // hash
+                "03",             // validation_pass, int8
+                "0f",             // Right
+                &"33".repeat(32), // its left hash
+                "f0",             // Left
+                "00",             // Op
+                &"33".repeat(32), // right hash of the Left step
+                "00000000",       // operations, size of the list in bytes
+            ]
+            .concat(),
+            json!({
+                "kind": "Operations_for_blocks",
+                "operations_for_block": {"hash": GENESIS, "validation_pass": 3},
+                "operation_hashes_path": {"left": llo, "path": {"path": {}, "right": llo}},
+                "operations": []
+            }),
+        )?;
+        assert!(from_bytes::<Path>(&[0xf0; 64]).is_err());
+        assert!(from_bytes::<Path>(&[0x01]).is_err());
+        Ok(())
+    }
+}
@@ -1,12 +1,21 @@
+use std::net::SocketAddr;
+
 use crypto_box::{self, aead::rand_core::CryptoRngCore};
 use serde::{Deserialize, Serialize};
 
+use crate::encoding::direct::TezosEncoding;
+use version::NetworkVersion;
+
 pub mod binserde;
+pub mod frame;
 pub mod handshake;
+pub mod message;
+pub mod registry;
+pub mod version;
 
 /// Newtype for Nonce, allowing implementation of binary serialization
 /// when transferred in p2p messages
-#[derive(Debug, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub struct Nonce(crypto_box::Nonce);
 
 impl Nonce {
@@ -18,6 +27,12 @@ impl Nonce {
         rng.fill_bytes(&mut bytes);
         Nonce::from(bytes)
     }
+
+    pub fn bytes(&self) -> [u8; 24] {
+        let mut bytes = [0; 24];
+        bytes.copy_from_slice(self.as_ref());
+        bytes
+    }
 }
 
 impl Nonce {
@@ -34,9 +49,24 @@ impl Nonce {
     pub fn inc_step(&mut self, step: u16) {
         self.inc_byteno(22, step);
     }
+    /// Adds `value` to the nonce, 16 bits at a time
+    /// ```rust
+    /// use tzhandhsake::p2p::Nonce;
+    /// let mut nonce = Nonce::from([0;24]);
+    /// nonce.add(0x1_0000_ffff);
+    /// assert_eq!(&nonce.as_ref()[18..], &[0, 1, 0, 0, 0xff, 0xff]);
+    /// ```
+    pub fn add(&mut self, value: u64) {
+        for limb in 0..4 {
+            let step = (value >> (16 * limb)) as u16;
+            if step != 0 {
+                self.inc_byteno(22 - 2 * limb, step);
+            }
+        }
+    }
     fn inc_byteno(&mut self, byteno: usize, step: u16) {
         assert!(byteno < 24, "overflow");
-        assert!(byteno % 2 == 0, "byteno should be even");
+        assert!(byteno.is_multiple_of(2), "byteno should be even");
         let mut step = step as u32;
         let mut byteno = byteno;
         loop {
@@ -100,23 +130,35 @@ impl Default for PublicKey {
 }
 
 /// Ghostnet default chain name
-const DEFAULT_CHAIN: &str = "TEZOS_ITHACANET_2022-01-25T15:00:00Z";
+pub(crate) const DEFAULT_CHAIN: &str = "TEZOS_ITHACANET_2022-01-25T15:00:00Z";
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TezosEncoding)]
 pub struct ChainName(String);
 impl Default for ChainName {
     fn default() -> Self {
         Self(DEFAULT_CHAIN.to_string())
     }
 }
+impl From<&str> for ChainName {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+impl std::fmt::Display for ChainName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
 impl AsRef<[u8]> for ChainName {
     fn as_ref(&self) -> &[u8] {
         self.0.as_bytes()
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct P2PVersion(u16);
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, TezosEncoding,
+)]
+pub struct P2PVersion(pub u16);
 
 impl Default for P2PVersion {
     fn default() -> Self {
@@ -124,8 +166,10 @@ impl Default for P2PVersion {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct DDBVersion(u16);
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, TezosEncoding,
+)]
+pub struct DDBVersion(pub u16);
 
 impl Default for DDBVersion {
     fn default() -> Self {
@@ -133,21 +177,27 @@ impl Default for DDBVersion {
     }
 }
 
-/// Metadata is actuall 2 booleans
+/// Connection metadata exchanged right after the `ConnectionMessage`
 /// `src/lib_p2p_services/connection_metadata.ml`
-/// (disable_mempool, private_node)
-#[derive(Debug, PartialEq, Default)]
-pub struct Metadata([u8; 2]);
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, TezosEncoding)]
+pub struct Metadata {
+    /// The peer doesn't want to receive operations
+    pub disable_mempool: bool,
+    /// The peer must not be advertised to other peers
+    pub private_node: bool,
+}
 
-#[derive(Debug, PartialEq, Serialize, Deserialize, Default)]
+/// First message of the handshake, json fields are named like octez
+/// `src/lib_p2p/p2p_socket.ml`
+#[derive(Debug, PartialEq, Serialize, Deserialize, Default, TezosEncoding)]
 pub struct ConnectionMessage {
     pub(crate) port: u16,
+    #[serde(rename = "pubkey")]
     pub(crate) public_key: PublicKey,
     pub(crate) proof_of_work_stamp: Nonce,
+    #[serde(rename = "message_nonce")]
     pub(crate) nonce: Nonce,
-    pub(crate) chain_name: ChainName,
-    pub(crate) distributed_db_version: DDBVersion,
-    pub(crate) p2p_version: P2PVersion,
+    pub(crate) version: NetworkVersion,
 }
 
 impl ConnectionMessage {
@@ -156,8 +206,82 @@ impl ConnectionMessage {
     }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize, Default)]
-pub struct Ack(bool);
+/// Last message of the handshake, tells whether the peer accepts the connection
+/// `src/lib_p2p/p2p_socket.ml`
+#[derive(Debug, Clone, PartialEq, Default, TezosEncoding)]
+pub enum AckMessage {
+    #[default]
+    #[encoding(tag = 0)]
+    Ack,
+    /// Refusal from older nodes, `Nack_v_0` in octez
+    #[encoding(tag = 0xff)]
+    NackV0,
+    #[encoding(tag = 1)]
+    Nack {
+        motive: NackMotive,
+        /// Points the peer suggests to connect to instead, `nack_list` in octez
+        #[encoding(dynamic, max_length = 100)]
+        potential_peers_to_connect: Vec<SocketAddr>,
+    },
+}
+
+/// Reason of a refusal, encoded as a u16
+/// `src/lib_p2p_base/p2p_rejection.ml`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NackMotive {
+    NoMotive,
+    TooManyConnections,
+    UnknownChainName,
+    DeprecatedP2pVersion,
+    DeprecatedDistributedDbVersion,
+    AlreadyConnected,
+    /// Motive added in a newer version of octez
+    Unknown(u16),
+}
+
+impl From<u16> for NackMotive {
+    fn from(value: u16) -> Self {
+        match value {
+            0 => NackMotive::NoMotive,
+            1 => NackMotive::TooManyConnections,
+            2 => NackMotive::UnknownChainName,
+            3 => NackMotive::DeprecatedP2pVersion,
+            4 => NackMotive::DeprecatedDistributedDbVersion,
+            5 => NackMotive::AlreadyConnected,
+            v => NackMotive::Unknown(v),
+        }
+    }
+}
+
+impl From<NackMotive> for u16 {
+    fn from(value: NackMotive) -> Self {
+        match value {
+            NackMotive::NoMotive => 0,
+            NackMotive::TooManyConnections => 1,
+            NackMotive::UnknownChainName => 2,
+            NackMotive::DeprecatedP2pVersion => 3,
+            NackMotive::DeprecatedDistributedDbVersion => 4,
+            NackMotive::AlreadyConnected => 5,
+            NackMotive::Unknown(v) => v,
+        }
+    }
+}
+
+impl std::fmt::Display for NackMotive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NackMotive::NoMotive => f.write_str("no motive"),
+            NackMotive::TooManyConnections => f.write_str("too many connections"),
+            NackMotive::UnknownChainName => f.write_str("unknown chain name"),
+            NackMotive::DeprecatedP2pVersion => f.write_str("deprecated p2p version"),
+            NackMotive::DeprecatedDistributedDbVersion => {
+                f.write_str("deprecated distributed db version")
+            }
+            NackMotive::AlreadyConnected => f.write_str("already connected"),
+            NackMotive::Unknown(v) => write!(f, "unknown motive {}", v),
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
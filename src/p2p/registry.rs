@@ -0,0 +1,111 @@
+/// Messages the CLI knows by name, to describe, decode and encode them.
+///
+use anyhow::{bail, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::encoding::{
+    direct::{self, TezosEncoding},
+    schema::Schema,
+    span::Span,
+};
+
+use super::{
+    message::{BlockHeader, Operation, PeerMessage},
+    AckMessage, ConnectionMessage, Metadata,
+};
+
+/// Binary and json encodings of a message, without knowing its type
+#[derive(Clone, Copy)]
+pub struct Codec {
+    pub name: &'static str,
+    pub schema: fn() -> Schema,
+    /// Binary to json
+    pub decode: fn(&[u8]) -> Result<Value>,
+    /// Binary to json, with the spans of the decoded fields
+    pub decode_spans: fn(&[u8]) -> (Result<Value>, Span),
+    /// Json to binary
+    pub encode: fn(Value) -> Result<Vec<u8>>,
+}
+
+impl Codec {
+    fn of<T>(name: &'static str) -> Self
+    where
+        T: TezosEncoding + Serialize + DeserializeOwned,
+    {
+        Codec {
+            name,
+            schema: T::schema,
+            decode: |bytes| Ok(serde_json::to_value(direct::from_bytes::<T>(bytes)?)?),
+            decode_spans: |bytes| {
+                let (value, span) = direct::decode_spans::<T>(bytes);
+                let json = value
+                    .map_err(Into::into)
+                    .and_then(|value| Ok(serde_json::to_value(value)?));
+                (json, span)
+            },
+            encode: |json| {
+                Ok(direct::to_bytes_no_header(&serde_json::from_value::<T>(
+                    json,
+                )?)?)
+            },
+        }
+    }
+}
+
+/// Every message, handshake messages first
+pub fn codecs() -> Vec<Codec> {
+    vec![
+        Codec::of::<ConnectionMessage>("connection_message"),
+        Codec::of::<Metadata>("metadata"),
+        Codec::of::<AckMessage>("ack"),
+        Codec::of::<PeerMessage>("peer_message"),
+        Codec::of::<BlockHeader>("block_header"),
+        Codec::of::<Operation>("operation"),
+    ]
+}
+
+pub fn codec(name: &str) -> Result<Codec> {
+    match codecs().into_iter().find(|codec| codec.name == name) {
+        Some(codec) => Ok(codec),
+        None => bail!("unknown message `{}`, see `describe` for the list", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use serde_json::json;
+
+    use super::{codec, codecs};
+
+    #[test]
+    fn it_round_trips_through_json() -> Result<()> {
+        let ack = codec("ack")?;
+        let nack = json!({"nack_motive": 1, "nack_list": ["127.0.0.1:9732"]});
+        let bytes = (ack.encode)(nack.clone())?;
+        assert_eq!(&[1, 0, 1, 0, 0, 0, 18, 0, 0, 0, 14], &bytes[..11]);
+        assert_eq!(nack, (ack.decode)(&bytes)?);
+        assert_eq!(json!({"nack_v_0": {}}), (ack.decode)(&[0xff])?);
+
+        let metadata = codec("metadata")?;
+        assert_eq!(
+            json!({"disable_mempool": false, "private_node": true}),
+            (metadata.decode)(&[0, 0xff])?
+        );
+        assert!((metadata.decode)(&[0, 0xff, 0]).is_err());
+        assert!(codec("block").is_err());
+        assert_eq!(
+            vec![
+                "connection_message",
+                "metadata",
+                "ack",
+                "peer_message",
+                "block_header",
+                "operation"
+            ],
+            codecs().iter().map(|codec| codec.name).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+/// Network version negotiation, same rules as octez `src/lib_p2p_services/network_version.ml`
+///
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    encoding::direct::TezosEncoding,
+    network::{Network, NetworkError},
+};
+
+use super::{ChainName, DDBVersion, NackMotive, P2PVersion};
+
+/// Version announced in the `ConnectionMessage`, and the one selected for the connection
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default, TezosEncoding)]
+pub struct NetworkVersion {
+    pub chain_name: ChainName,
+    pub distributed_db_version: DDBVersion,
+    pub p2p_version: P2PVersion,
+}
+
+/// Versions we are able to speak on a chain
+#[derive(Debug, Clone, PartialEq)]
+pub struct SupportedVersions {
+    chain_name: ChainName,
+    distributed_db_versions: Vec<DDBVersion>,
+    p2p_versions: Vec<P2PVersion>,
+}
+
+impl Default for SupportedVersions {
+    /// Versions supported by current octez nodes on ghostnet
+    fn default() -> Self {
+        Network::default()
+            .versions()
+            .expect("presets have versions")
+    }
+}
+
+impl SupportedVersions {
+    /// Fails when a list of versions is empty, there would be nothing to announce.
+    pub fn new(
+        chain_name: ChainName,
+        distributed_db_versions: Vec<DDBVersion>,
+        p2p_versions: Vec<P2PVersion>,
+    ) -> Result<Self, NetworkError> {
+        if distributed_db_versions.is_empty() || p2p_versions.is_empty() {
+            return Err(NetworkError::NoVersion);
+        }
+        Ok(Self {
+            chain_name,
+            distributed_db_versions,
+            p2p_versions,
+        })
+    }
+
+    pub fn chain_name(&self) -> &ChainName {
+        &self.chain_name
+    }
+
+    /// We announce our greatest versions, the peer picks what it supports.
+    pub fn announced(&self) -> NetworkVersion {
+        NetworkVersion {
+            chain_name: self.chain_name.clone(),
+            distributed_db_version: greatest(&self.distributed_db_versions),
+            p2p_version: greatest(&self.p2p_versions),
+        }
+    }
+
+    /// Picks the version to use with a peer from what it announced, or the motive to nack it.
+    pub fn select(&self, remote: &NetworkVersion) -> Result<NetworkVersion, NackMotive> {
+        if self.chain_name != remote.chain_name {
+            return Err(NackMotive::UnknownChainName);
+        }
+        Ok(NetworkVersion {
+            chain_name: self.chain_name.clone(),
+            distributed_db_version: may_select_version(
+                &self.distributed_db_versions,
+                &remote.distributed_db_version,
+                NackMotive::DeprecatedDistributedDbVersion,
+            )?,
+            p2p_version: may_select_version(
+                &self.p2p_versions,
+                &remote.p2p_version,
+                NackMotive::DeprecatedP2pVersion,
+            )?,
+        })
+    }
+}
+
+fn greatest<V: Ord + Clone>(versions: &[V]) -> V {
+    versions
+        .iter()
+        .max()
+        .cloned()
+        .expect("versions are never empty")
+}
+
+/// Our best version if the peer announced at least that, otherwise the peer's version if we
+/// support it.
+fn may_select_version<V: Ord + Clone>(
+    accepted: &[V],
+    remote: &V,
+    motive: NackMotive,
+) -> Result<V, NackMotive> {
+    let best = greatest(accepted);
+    if &best <= remote {
+        Ok(best)
+    } else if accepted.contains(remote) {
+        Ok(remote.clone())
+    } else {
+        Err(motive)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        network::NetworkError,
+        p2p::{ChainName, DDBVersion, NackMotive, P2PVersion},
+    };
+
+    use super::{NetworkVersion, SupportedVersions};
+
+    #[test]
+    fn it_selects_versions_like_octez() {
+        let supported = SupportedVersions::new(
+            ChainName::default(),
+            vec![DDBVersion(1), DDBVersion(2)],
+            vec![P2PVersion(0), P2PVersion(1)],
+        )
+        .unwrap();
+        assert!(matches!(
+            SupportedVersions::new(ChainName::default(), vec![DDBVersion(2)], vec![]),
+            Err(NetworkError::NoVersion)
+        ));
+        let announced = supported.announced();
+        assert_eq!(DDBVersion(2), announced.distributed_db_version);
+        assert_eq!(P2PVersion(1), announced.p2p_version);
+        assert_eq!(Ok(announced.clone()), supported.select(&announced));
+
+        // a newer peer speaks our best version
+        let newer = NetworkVersion {
+            distributed_db_version: DDBVersion(3),
+            p2p_version: P2PVersion(2),
+            ..announced.clone()
+        };
+        assert_eq!(Ok(announced.clone()), supported.select(&newer));
+
+        // an older peer is fine as long as we still support its version
+        let older = NetworkVersion {
+            distributed_db_version: DDBVersion(1),
+            ..announced.clone()
+        };
+        assert_eq!(Ok(older.clone()), supported.select(&older));
+
+        let deprecated = NetworkVersion {
+            distributed_db_version: DDBVersion(0),
+            ..announced.clone()
+        };
+        assert_eq!(
+            Err(NackMotive::DeprecatedDistributedDbVersion),
+            supported.select(&deprecated)
+        );
+        let other_chain = NetworkVersion {
+            chain_name: ChainName::from("TEZOS_MAINNET"),
+            ..announced
+        };
+        assert_eq!(
+            Err(NackMotive::UnknownChainName),
+            supported.select(&other_chain)
+        );
+    }
+}
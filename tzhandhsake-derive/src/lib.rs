@@ -0,0 +1,422 @@
+//! `#[derive(TezosEncoding)]`, generates the octez binary encoding of a type from its
+//! definition, see `tzhandhsake::encoding::direct`.
+//!
+//! Attributes:
+//! - `#[encoding(tag_size = "u16")]` on enums, tags are u8 by default
+//! - `#[encoding(tag = 0xff)]` on variants, the tag defaults to the variant index
+//! - `#[encoding(dynamic)]` or `#[encoding(dynamic = "u8" | "u16" | "u30" | "u32")]` on fields,
+//!   prefixes the field with its size, u30 by default
+//! - `#[encoding(bound = 128)]` on fields, maximum size in bytes of the field
+//! - `#[encoding(fixed = 32)]` on `Vec` fields, exact number of elements, without prefix
+//! - `#[encoding(max_length = 100)]` on `Vec` fields, maximum number of elements
+//!
+//! The generated `schema()` follows the same attributes, tuple structs with a single field
+//! are described as their field.
+use proc_macro::TokenStream;
+use proc_macro2::{Literal, Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Fields, Ident, LitInt, LitStr,
+    Type,
+};
+
+#[proc_macro_derive(TezosEncoding, attributes(encoding))]
+pub fn derive_tezos_encoding(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn krate() -> TokenStream2 {
+    quote!(::tzhandhsake::encoding::direct)
+}
+
+#[derive(Default)]
+struct FieldAttrs {
+    dynamic: Option<Ident>,
+    bound: Option<LitInt>,
+    fixed: Option<LitInt>,
+    max_length: Option<LitInt>,
+}
+
+impl FieldAttrs {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut parsed = FieldAttrs::default();
+        for attr in attrs.iter().filter(|a| a.path().is_ident("encoding")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("dynamic") {
+                    let prefix = if meta.input.peek(syn::Token![=]) {
+                        let kind: LitStr = meta.value()?.parse()?;
+                        match kind.value().as_str() {
+                            "u8" => "U8",
+                            "u16" => "U16",
+                            "u30" => "U30",
+                            "u32" => "U32",
+                            _ => {
+                                return Err(
+                                    meta.error("expected \"u8\", \"u16\", \"u30\" or \"u32\"")
+                                )
+                            }
+                        }
+                    } else {
+                        "U30"
+                    };
+                    parsed.dynamic = Some(Ident::new(prefix, Span::call_site()));
+                    Ok(())
+                } else if meta.path.is_ident("bound") {
+                    parsed.bound = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else if meta.path.is_ident("fixed") {
+                    parsed.fixed = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else if meta.path.is_ident("max_length") {
+                    parsed.max_length = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error(
+                        "unknown field attribute, expected dynamic, bound, fixed or max_length",
+                    ))
+                }
+            })?;
+        }
+        Ok(parsed)
+    }
+
+    /// Statement encoding `value`, a reference to the field, into `out`
+    fn encode(&self, value: TokenStream2) -> TokenStream2 {
+        let krate = krate();
+        let mut encode = match (&self.fixed, &self.max_length) {
+            (Some(n), _) => quote!(#krate::encode_fixed(#value, #n, out)?),
+            (None, Some(max)) => quote!(#krate::encode_max_length(#value, #max, out)?),
+            (None, None) => quote!(#krate::TezosEncoding::encode(#value, out)?),
+        };
+        if let Some(bound) = &self.bound {
+            encode = quote!(out.bounded(#bound, |out| {
+                #encode;
+                Ok(())
+            })?);
+        }
+        if let Some(prefix) = &self.dynamic {
+            encode = quote!(out.dynamic(#krate::SizePrefix::#prefix, |out| {
+                #encode;
+                Ok(())
+            })?);
+        }
+        quote!(#encode;)
+    }
+
+    /// Expression decoding the field from `input`, a `Result`
+    fn decode(&self) -> TokenStream2 {
+        let krate = krate();
+        let mut decode = match (&self.fixed, &self.max_length) {
+            (Some(n), _) => quote!(#krate::decode_fixed(input, #n)),
+            (None, Some(max)) => quote!(#krate::decode_max_length(input, #max)),
+            (None, None) => quote!(#krate::TezosEncoding::decode(input)),
+        };
+        if let Some(bound) = &self.bound {
+            decode = quote!(input.bounded(#bound, |input| #decode));
+        }
+        if let Some(prefix) = &self.dynamic {
+            decode = quote!(input.dynamic(#krate::SizePrefix::#prefix, |input| #decode));
+        }
+        decode
+    }
+
+    /// Expression describing the field of type `ty`
+    fn schema(&self, ty: &Type) -> TokenStream2 {
+        let krate = krate();
+        let mut schema = quote!(<#ty as #krate::TezosEncoding>::schema());
+        if let Some(n) = &self.fixed {
+            schema = quote!(#schema.fixed(#n));
+        } else if let Some(max) = &self.max_length {
+            schema = quote!(#schema.max_length(#max));
+        }
+        if let Some(bound) = &self.bound {
+            schema = quote!(#krate::Schema::Bounded {
+                bound: #bound,
+                value: ::std::boxed::Box::new(#schema),
+            });
+        }
+        if let Some(prefix) = &self.dynamic {
+            schema = quote!(#krate::Schema::Dynamic {
+                prefix: #krate::SizePrefix::#prefix,
+                value: ::std::boxed::Box::new(#schema),
+            });
+        }
+        schema
+    }
+}
+
+enum TagSize {
+    U8,
+    U16,
+}
+
+impl TagSize {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut size = TagSize::U8;
+        for attr in attrs.iter().filter(|a| a.path().is_ident("encoding")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("tag_size") {
+                    let kind: LitStr = meta.value()?.parse()?;
+                    size = match kind.value().as_str() {
+                        "u8" => TagSize::U8,
+                        "u16" => TagSize::U16,
+                        _ => return Err(meta.error("expected \"u8\" or \"u16\"")),
+                    };
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown enum attribute, expected tag_size"))
+                }
+            })?;
+        }
+        Ok(size)
+    }
+
+    fn literal(&self, tag: u16, span: Span) -> syn::Result<Literal> {
+        match self {
+            TagSize::U8 => u8::try_from(tag).map(Literal::u8_suffixed).map_err(|_| {
+                syn::Error::new(span, "tag doesn't fit in a u8, use tag_size = \"u16\"")
+            }),
+            TagSize::U16 => Ok(Literal::u16_suffixed(tag)),
+        }
+    }
+
+    fn bytes(&self) -> u8 {
+        match self {
+            TagSize::U8 => 1,
+            TagSize::U16 => 2,
+        }
+    }
+
+    fn ty(&self) -> TokenStream2 {
+        match self {
+            TagSize::U8 => quote!(u8),
+            TagSize::U16 => quote!(u16),
+        }
+    }
+}
+
+fn variant_tag(attrs: &[Attribute], index: usize) -> syn::Result<(u16, Span)> {
+    let mut tag = None;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("encoding")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("tag") {
+                let lit: LitInt = meta.value()?.parse()?;
+                tag = Some((lit.base10_parse()?, lit.span()));
+                Ok(())
+            } else {
+                Err(meta.error("unknown variant attribute, expected tag"))
+            }
+        })?;
+    }
+    match tag {
+        Some(tag) => Ok(tag),
+        None => u16::try_from(index)
+            .map(|index| (index, Span::call_site()))
+            .map_err(|_| syn::Error::new(Span::call_site(), "too many variants")),
+    }
+}
+
+/// Encoding of the fields bound to `names`: the statements writing them, the expression
+/// building the value back with `constructor`, and their schemas.
+struct EncodedFields {
+    encode: Vec<TokenStream2>,
+    build: TokenStream2,
+    schemas: Vec<TokenStream2>,
+}
+
+fn fields(
+    fields: &Fields,
+    names: &[Ident],
+    constructor: TokenStream2,
+) -> syn::Result<EncodedFields> {
+    let krate = krate();
+    let mut encode = vec![];
+    let mut decode = vec![];
+    let mut schemas = vec![];
+    // single fields of tuples are transparent, like in their schema
+    let transparent = matches!(fields, Fields::Unnamed(unnamed) if unnamed.unnamed.len() == 1);
+    for (index, (field, name)) in fields.iter().zip(names).enumerate() {
+        let attrs = FieldAttrs::parse(&field.attrs)?;
+        encode.push(attrs.encode(quote!(#name)));
+        let value = attrs.decode();
+        decode.push(match &field.ident {
+            Some(ident) => quote!(#ident: input.span(stringify!(#ident), |input| #value)?),
+            None if transparent => quote!(#value?),
+            None => {
+                let index = Literal::usize_unsuffixed(index);
+                quote!(input.span(#index, |input| #value)?)
+            }
+        });
+        let schema = attrs.schema(&field.ty);
+        let name = match &field.ident {
+            Some(ident) => quote!(::std::option::Option::Some(stringify!(#ident))),
+            None => quote!(::std::option::Option::None),
+        };
+        schemas.push(quote!(#krate::Field {
+            name: #name,
+            schema: #schema,
+        }));
+    }
+    let build = match fields {
+        Fields::Named(_) => quote!(#constructor { #(#decode),* }),
+        Fields::Unnamed(_) => quote!(#constructor ( #(#decode),* )),
+        Fields::Unit => constructor,
+    };
+    Ok(EncodedFields {
+        encode,
+        build,
+        schemas,
+    })
+}
+
+fn field_names(fields: &Fields) -> Vec<Ident> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| {
+            f.ident
+                .clone()
+                .unwrap_or_else(|| format_ident!("field_{}", i))
+        })
+        .collect()
+}
+
+/// Pattern binding the fields to `names`
+fn pattern(fields: &Fields, path: TokenStream2, names: &[Ident]) -> TokenStream2 {
+    match fields {
+        Fields::Named(_) => quote!(#path { #(#names),* }),
+        Fields::Unnamed(_) => quote!(#path ( #(#names),* )),
+        Fields::Unit => path,
+    }
+}
+
+fn expand(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let krate = krate();
+    let name = &input.ident;
+    for param in input.generics.type_params_mut() {
+        param.bounds.push(parse_quote!(#krate::TezosEncoding));
+    }
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let (encode, decode, schema) = match &input.data {
+        Data::Struct(data) => {
+            let names = field_names(&data.fields);
+            let EncodedFields {
+                encode,
+                build,
+                schemas,
+            } = fields(&data.fields, &names, quote!(Self))?;
+            let pattern = pattern(&data.fields, quote!(Self), &names);
+            let schema = match &data.fields {
+                Fields::Unnamed(unnamed) if unnamed.unnamed.len() == 1 => {
+                    let field = &data.fields.iter().next().expect("one field");
+                    FieldAttrs::parse(&field.attrs)?.schema(&field.ty)
+                }
+                _ => quote!(#krate::Schema::Obj {
+                    name: stringify!(#name),
+                    fields: ::std::vec![#(#schemas),*],
+                }),
+            };
+            (
+                quote! {
+                    let #pattern = self;
+                    #(#encode)*
+                    Ok(())
+                },
+                quote!(Ok(#build)),
+                schema,
+            )
+        }
+        Data::Enum(data) => {
+            let tag_size = TagSize::parse(&input.attrs)?;
+            let tag_ty = tag_size.ty();
+            let tag_bytes = tag_size.bytes();
+            let mut seen = vec![];
+            let mut encode_arms = vec![];
+            let mut decode_arms = vec![];
+            let mut cases = vec![];
+            for (index, variant) in data.variants.iter().enumerate() {
+                let (tag, span) = variant_tag(&variant.attrs, index)?;
+                if seen.contains(&tag) {
+                    return Err(syn::Error::new(span, format!("duplicated tag {}", tag)));
+                }
+                seen.push(tag);
+                let tag_value = Literal::u16_suffixed(tag);
+                let tag = tag_size.literal(tag, span)?;
+                let ident = &variant.ident;
+                let names = field_names(&variant.fields);
+                let EncodedFields {
+                    encode,
+                    build,
+                    schemas,
+                } = fields(&variant.fields, &names, quote!(Self::#ident))?;
+                let pattern = pattern(&variant.fields, quote!(Self::#ident), &names);
+                encode_arms.push(quote! {
+                    #pattern => {
+                        #krate::TezosEncoding::encode(&#tag, out)?;
+                        #(#encode)*
+                    }
+                });
+                decode_arms.push(quote! {
+                    #tag => input.span_from(start, stringify!(#ident), |input| Ok(#build)),
+                });
+                cases.push(quote!(#krate::Case {
+                    tag: #tag_value,
+                    name: stringify!(#ident),
+                    fields: ::std::vec![#(#schemas),*],
+                }));
+            }
+            (
+                quote! {
+                    match self {
+                        #(#encode_arms)*
+                    }
+                    Ok(())
+                },
+                quote! {
+                    // the span of the variant includes its tag
+                    let start = input.position();
+                    match <#tag_ty as #krate::TezosEncoding>::decode(input)? {
+                        #(#decode_arms)*
+                        tag => Err(#krate::Error::UnknownTag {
+                            name: stringify!(#name),
+                            tag: tag.into(),
+                        }),
+                    }
+                },
+                quote!(#krate::Schema::Union {
+                    name: stringify!(#name),
+                    tag_size: #tag_bytes,
+                    cases: ::std::vec![#(#cases),*],
+                }),
+            )
+        }
+        Data::Union(_) => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "unions can't derive TezosEncoding",
+            ))
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics #krate::TezosEncoding for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn encode(&self, out: &mut #krate::BinWriter) -> ::std::result::Result<(), #krate::Error> {
+                #encode
+            }
+
+            #[allow(unused_variables)]
+            fn decode(input: &mut #krate::BinReader<'_>) -> ::std::result::Result<Self, #krate::Error> {
+                #decode
+            }
+
+            fn schema() -> #krate::Schema {
+                #schema
+            }
+        }
+    })
+}